@@ -1,39 +1,1210 @@
+#define _GNU_SOURCE
 #include <stdio.h>
+#include <stdarg.h>
 #include <stdlib.h>
 #include <stdint.h>
+#include <inttypes.h>
+#include <string.h>
+#include <errno.h>
+#include <time.h>
 #include <math.h>
 #include <argp.h>
+#include <pthread.h>
+#include <glob.h>
+#include <unistd.h>
+#include <sys/mman.h>
+#include <sys/stat.h>
+#include <sys/inotify.h>
+#include <poll.h>
+#include <zlib.h>
+#include <lzma.h>
 
-#define BUFFER_SIZE 256
+#define DEFAULT_BUFFER_SIZE (64 * 1024)
+#define DEFAULT_PRECISION 6
+#define MAX_PRECISION 17
+#define MAX_BUFFER_SIZE (64 * 1024 * 1024)
+#define MAX_NGRAM_SIZES 8
+#define MAX_XOR_KEY 64
+#define MAX_THREADS 256
+#define PARALLEL_MIN_BYTES (64 * 1024 * 1024)
+#define PROGRESS_MIN_BYTES (16 * 1024 * 1024)
+#define PROGRESS_WIDTH 30
+#define MAGIC_LENGTH 16
+#define WATCH_DEBOUNCE_MS 200
+#define MAX_CLUSTERS 64
+#define LZ_MAX_LENGTH (1024 * 1024)
+#define SAMPLE_BLOCK_SIZE 4096
+#define DIGRAM_CELLS 64
+#define MAX_SAMPLE_BLOCKS (1024 * 1024)
+#define DEFAULT_HISTOGRAM_BINS 16
+#define MAX_HISTOGRAM_BINS 256
+#define HISTOGRAM_WIDTH 40
+
+// Largest possible entropies: 256 equally likely byte values, or 9 equally
+// likely set-bit counts for the bit-level measure.
+#define MAX_BYTE_ENTROPY 8.0
+#define MAX_BIT_ENTROPY 3.169925001442312 // log2(9)
+
+// Colors for normalized entropy in text output: green below 75% of the
+// maximum, yellow below 7.5 bits per byte (93.75%), red above.
+#define COLOR_YELLOW_FROM 0.75
+#define COLOR_RED_FROM 0.9375
+#define COLOR_GREEN "\033[32m"
+#define COLOR_YELLOW "\033[33m"
+#define COLOR_RED "\033[31m"
+#define COLOR_RESET "\033[0m"
+
+// Thresholds used by --classify. Random data gives a chi-square near 255
+// (255 degrees of freedom); 330.5 is its 99.9th percentile.
+#define CLASSIFY_MIN_BYTES 256
+#define CLASSIFY_TEXT_PRINTABLE 0.95
+#define CLASSIFY_COMPRESSED_ENTROPY 7.5
+#define CLASSIFY_ENCRYPTED_ENTROPY 7.9
+#define CLASSIFY_ENCRYPTED_CHI_SQUARE 330.5
 
 const char *argp_program_version = "entropy_calculator 0.10";
 const char *argp_program_bug_address = "<bug-gnu-utils@gnu.org>";
 static char doc[] = "Entropy calculator for files, calculates either byte-level or bit-level entropy based on a command-line argument.";
 static char args_doc[] = "FILE...";
+
+enum option_key {
+    OPT_EXPLAIN = 0x100,
+    OPT_NGRAM,
+    OPT_RELATIVE_TO,
+    OPT_CONDITIONAL,
+    OPT_MILLIBITS,
+    OPT_PRECISION,
+    OPT_TRANSFORM,
+    OPT_THRESHOLD_MIN,
+    OPT_THRESHOLD_MAX,
+    OPT_RATE,
+    OPT_LABEL,
+    OPT_ENTROPY_ONLY,
+    OPT_REFERENCE,
+    OPT_PRINT_SCHEMA,
+    OPT_CACHE,
+    OPT_DELTA_ENTROPY,
+    OPT_MONTE_CARLO,
+    OPT_LZ,
+    OPT_BIT_STREAM,
+    OPT_CODEPOINT,
+    OPT_ALL_METRICS,
+    OPT_TIMINGS,
+    OPT_ALPHABET,
+    OPT_INCLUDE_HISTOGRAM,
+    OPT_DIGRAM_HEATMAP,
+    OPT_CLASSIFY,
+    OPT_THREADS,
+    OPT_BUFFER_SIZE,
+    OPT_ENTROPY_HISTOGRAM,
+    OPT_DISTRIBUTION_DIFF,
+    OPT_CLUSTER,
+    OPT_MIN_SIZE,
+    OPT_OFFSET,
+    OPT_LENGTH,
+    OPT_SAMPLE,
+    OPT_SEED,
+    OPT_NO_COLOR,
+    OPT_NO_PROGRESS,
+    OPT_SORT,
+    OPT_REVERSE,
+    OPT_TOP,
+    OPT_FILES_FROM,
+    OPT_SORT_INPUT,
+    OPT_WATCH,
+    OPT_DRY_RUN,
+    OPT_ARCHIVES,
+    OPT_DECOMPRESS,
+    OPT_ALLOW_DEVICES,
+    OPT_FAIL_FAST,
+    OPT_HISTOGRAM_SVG,
+    OPT_HISTOGRAM_BINS,
+    OPT_HISTOGRAM_LOG,
+    OPT_BOTTOM,
+    OPT_MAX_SIZE,
+};
+
+struct analysis_cache;
+
+enum output_format {
+    FORMAT_TEXT,
+    FORMAT_MARKDOWN,
+    FORMAT_NDJSON,
+    FORMAT_HTML,
+    FORMAT_COMPACT,
+};
+
+// How much is written to stderr besides hard errors.
+enum log_level {
+    LOG_QUIET,              // Hard errors only
+    LOG_WARN,               // Also warnings (default)
+    LOG_INFO,               // Also skipped files and per-file timing (-v)
+};
+
+static enum log_level log_level = LOG_WARN;
+
+// Fractional digits of the metrics in text, compact, markdown and html output,
+// set by --precision. ndjson always keeps full precision.
+static int precision = DEFAULT_PRECISION;
+
+static void log_message(enum log_level level, const char *format, va_list args) {
+    if (log_level < level) {
+        return;
+    }
+    vfprintf(stderr, format, args);
+    fputc('\n', stderr);
+}
+
+static void log_warn(const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    log_message(LOG_WARN, format, args);
+    va_end(args);
+}
+
+static void log_info(const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    log_message(LOG_INFO, format, args);
+    va_end(args);
+}
+
+enum sort_key {
+    SORT_NONE,
+    SORT_ENTROPY,
+    SORT_SIZE,
+    SORT_NAME,
+};
+
 static struct argp_option options[] = {
     {"bit", 'b', 0, 0, "Calculate bit-level informational entropy"},
+    {"verbose", 'v', 0, 0, "Also report skipped files and per-file timing on stderr"},
+    {"quiet", 'q', 0, 0, "Only report hard errors on stderr; no warnings or progress bar"},
+    {"explain", OPT_EXPLAIN, 0, 0, "Annotate each reported metric with its formula and meaning"},
+    {"ngram", OPT_NGRAM, "N", 0, "Also calculate the entropy of overlapping N-byte sequences (repeatable)"},
+    {"relative-to", OPT_RELATIVE_TO, "FILE", 0, "Report each file's entropy as a percentage of FILE's entropy"},
+    {"reference", OPT_REFERENCE, "uniform|PATH", 0, "Report how far each file's byte distribution is from uniform or from the 256 probabilities in the JSON array at PATH"},
+    {"conditional", OPT_CONDITIONAL, 0, 0, "Also calculate the entropy of each byte given the previous byte"},
+    {"delta-entropy", OPT_DELTA_ENTROPY, 0, 0, "Also calculate the entropy of the differences between consecutive bytes"},
+    {"delta", 0, 0, OPTION_ALIAS, 0},
+    {"all-metrics", OPT_ALL_METRICS, 0, 0, "Show both the byte-level and the bit-level entropy; --bit still picks the one used for sorting and percentages"},
+    {"monte-carlo", OPT_MONTE_CARLO, 0, 0, "Also estimate Pi from 6-byte points as ent does; the error is a randomness indicator"},
+    {"bit-stream", OPT_BIT_STREAM, 0, 0, "Also report the entropy of the file as a stream of single bits, overall and for each bit position; unlike --bit, which counts set bits per byte"},
+    {"codepoint", OPT_CODEPOINT, "MODE", OPTION_ARG_OPTIONAL, "Also calculate the entropy per Unicode character of UTF-8 input; invalid sequences count as U+FFFD (replace, the default) or make the file fail (strict)"},
+    {"lz", OPT_LZ, 0, 0, "Also report the Lempel-Ziv (LZ76) complexity of the first 1 MiB, which sees repetition that byte counts miss"},
+    {"format", 'f', "FORMAT", 0, "Output format: text (default), compact (one line per file), markdown, ndjson or html"},
+    {"entropy-only", OPT_ENTROPY_ONLY, 0, 0, "Print only the entropy of each file with 6 decimals unless --precision says otherwise, followed by a tab and the name when there is more than one file"},
+    {"label", OPT_LABEL, "TEXT", 0, "Tag the output with TEXT and the time of the run: a header line in text, markdown and html, label and timestamp keys in ndjson"},
+    {"output", 'o', "PATH", 0, "Write the results to PATH instead of stdout; diagnostics stay on stderr"},
+    {"transform", OPT_TRANSFORM, "xor:HEX|byteswap|delta", 0, "Also report the entropy after applying a transform to the bytes"},
+    {"threshold-min", OPT_THRESHOLD_MIN, "X", 0, "Exit with status 2 if any file's entropy per byte is below X"},
+    {"threshold-max", OPT_THRESHOLD_MAX, "X", 0, "Exit with status 2 if any file's entropy per byte is above X"},
+    {"rate-bytes-per-sec", OPT_RATE, "RATE", 0, "Also report the entropy rate in bits per second of a stream carrying RATE bytes per second"},
+    {"alphabet", OPT_ALPHABET, "FILE", 0, "Only count the byte values that occur in FILE; report other bytes as invalid"},
+    {"classify", OPT_CLASSIFY, 0, 0, "Guess whether each file is text, structured, compressed or encrypted"},
+    {"threads", OPT_THREADS, "N", 0, "Threads used to count files of 64 MiB or more (default: number of CPUs, 1 disables)"},
+    {"buffer-size", OPT_BUFFER_SIZE, "BYTES", 0, "Size of each read from the input (default 65536)"},
+    {"files-from", OPT_FILES_FROM, "PATH", 0, "Also analyze the paths listed one per line in PATH (- for stdin); blank lines and lines starting with # are ignored"},
+    {"sort-input", OPT_SORT_INPUT, 0, 0, "Analyze the files in byte-wise name order rather than the order they were given in, for reproducible output"},
+    {"cache", OPT_CACHE, "PATH", 0, "Keep the byte counts of analyzed files in PATH and reuse them while a file's size and modification time are unchanged"},
+    {"fail-fast", OPT_FAIL_FAST, 0, 0, "Stop at the first file that cannot be analyzed instead of going on with the rest"},
+    {"archives", OPT_ARCHIVES, 0, 0, "Analyze each file inside .zip, .tar, .tar.gz and .tgz inputs instead of the archive itself"},
+    {"decompress", OPT_DECOMPRESS, 0, 0, "Measure the uncompressed contents of gzip and xz inputs; other inputs are read as they are"},
+    {"allow-devices", OPT_ALLOW_DEVICES, 0, 0, "Also read block and character devices such as /dev/sdb, streaming them to the end (Unix only)"},
+    {"dry-run", OPT_DRY_RUN, 0, 0, "List the files that would be analyzed, with their sizes and a total, and exit without reading them"},
+    {"watch", OPT_WATCH, 0, 0, "Keep running and analyze the files again whenever one of them changes (Ctrl-C to stop)"},
+    {"sort", OPT_SORT, "KEY", 0, "Print results ordered by entropy, size or name (ascending); files that failed come last"},
+    {"reverse", OPT_REVERSE, 0, 0, "Reverse the --sort order"},
+    {"top", OPT_TOP, "N", 0, "Print only the first N analyzed files in --sort order (default order: highest entropy first)"},
+    {"bottom", OPT_BOTTOM, "N", 0, "Print only the last N analyzed files in --sort order (default order: highest entropy first)"},
+    {"no-color", OPT_NO_COLOR, 0, 0, "Never color text output (also disabled by NO_COLOR or when stdout is not a terminal)"},
+    {"no-progress", OPT_NO_PROGRESS, 0, 0, "Never show a progress bar (otherwise shown on a terminal for files of 16 MiB or more)"},
+    {"offset", OPT_OFFSET, "N", 0, "Start analyzing at byte N of each file (decimal or 0x hex, K/M/G allowed)"},
+    {"length", OPT_LENGTH, "N", 0, "Analyze only N bytes from --offset; the range must lie within the file"},
+    {"sample", OPT_SAMPLE, "N", 0, "Estimate from N blocks of 4096 bytes at random positions instead of reading whole files; smaller files are read in full"},
+    {"seed", OPT_SEED, "SEED", 0, "Seed for --sample positions, to repeat a sampled run (default: taken from the clock)"},
+    {"min-size", OPT_MIN_SIZE, "SIZE", 0, "Skip files smaller than SIZE bytes (K, M and G suffixes allowed)"},
+    {"max-size", OPT_MAX_SIZE, "SIZE", 0, "Skip files larger than SIZE bytes (K, M and G suffixes allowed)"},
+    {"entropy-histogram", OPT_ENTROPY_HISTOGRAM, "BINS", OPTION_ARG_OPTIONAL, "After several files, chart how their entropies are distributed over BINS bins from 0 to 8 bits (default 16)"},
+    {"cluster", OPT_CLUSTER, "K", 0, "After several files, group them into up to K clusters of similar entropy (1-D k-means)"},
+    {"distribution-diff", OPT_DISTRIBUTION_DIFF, "BINS", OPTION_ARG_OPTIONAL, "With exactly two files, chart their byte distributions back to back over BINS ranges of byte values (default 16, must divide 256)"},
+    {"timings", OPT_TIMINGS, 0, 0, "Report how long each file took to analyze"},
+    {"timing", 0, 0, OPTION_ALIAS, 0},
+    {"print-schema", OPT_PRINT_SCHEMA, "NAME", OPTION_ARG_OPTIONAL, "Print the JSON Schema of an ndjson output line and exit; NAME is result, the default and only schema"},
+    {"histogram-svg", OPT_HISTOGRAM_SVG, "PATH", 0, "Also draw the byte distribution of the (single) file as an SVG bar chart in PATH"},
+    {"histogram-bins", OPT_HISTOGRAM_BINS, "N", 0, "Bars in the byte distribution chart; N must divide 256 (default 256, one per byte value)"},
+    {"histogram-log", OPT_HISTOGRAM_LOG, 0, 0, "Scale the byte distribution chart by log(count + 1) so rare bytes stay visible"},
+    {"digram-heatmap", OPT_DIGRAM_HEATMAP, 0, 0, "Also draw how often each byte is followed by each other byte as a 64x64 grid (text output only)"},
+    {"include-histogram", OPT_INCLUDE_HISTOGRAM, 0, 0, "Include the non-zero byte counts in ndjson output"},
+    {"precision", OPT_PRECISION, "N", 0, "Digits after the decimal point for metrics in text, compact, markdown and html output (default 6); ndjson keeps full precision"},
+    {"millibits", OPT_MILLIBITS, 0, 0, "Report entropy values in ndjson as integer millibits (bits * 1000, rounded)"},
     {0}
 };
 
+enum transform_kind {
+    TRANSFORM_NONE,
+    TRANSFORM_XOR,
+    TRANSFORM_BYTESWAP,
+    TRANSFORM_DELTA,
+};
+
+struct transform {
+    enum transform_kind kind;
+    const char *spec;
+    uint8_t key[MAX_XOR_KEY];
+    size_t key_length;
+};
+
 struct arguments {
     char **files;
+    const char *files_from;
+    int watch;
+    int dry_run;
+    int archives;
+    int decompress;
+    int allow_devices;
+    int fail_fast;
+    int sort_input;
+    const char *cache_path;
+    struct analysis_cache *cache;
     int bit_level;
+    int all_metrics;
+    int explain;
+    size_t ngram_sizes[MAX_NGRAM_SIZES];
+    int ngram_count;
+    const char *relative_to;
+    int conditional;
+    int delta_entropy;
+    int monte_carlo;
+    int lz;
+    int bit_stream;
+    int codepoint;
+    int codepoint_strict;   // Invalid UTF-8 fails the file instead of counting as U+FFFD
+    enum output_format format;
+    int entropy_only;
+    int entropy_only_names; // Set in main when --entropy-only can print more than one result
+    int run_summary;        // Set in main for the first pass only, so --watch prints it once
+    int millibits;
+    struct transform transform;
+    double threshold_min;
+    double threshold_max;
+    double rate;            // Bytes per second for the entropy rate, 0 when not asked for
+    int timings;
+    const char *alphabet_file;
+    uint8_t alphabet[256];
+    const char *reference;
+    double reference_distribution[256];
+    const char *output;
+    const char *label;
+    char timestamp[32];     // Set in main before each run when --label is given
+    int include_histogram;
+    int digram_heatmap;
+    const char *histogram_svg;
+    int histogram_bins;
+    int histogram_log;
+    int classify;
+    int threads;
+    size_t buffer_size;
+    int entropy_histogram_bins;
+    int distribution_diff_bins;
+    int clusters;
+    enum sort_key sort;
+    int reverse;
+    unsigned long top;      // 0 prints every result
+    unsigned long bottom;
+    int no_color;
+    int color;              // Set in main from --no-color, NO_COLOR and whether stdout is a terminal
+    int no_progress;
+    int progress;           // Set in main from --no-progress and whether stderr is a terminal
+    uint64_t offset;
+    uint64_t length;        // UINT64_MAX reads to the end of the file
+    unsigned long sample_blocks; // 0 reads whole files
+    uint64_t seed;
+    int seed_set;
+    uint64_t min_size;
+    uint64_t max_size;
+};
+
+enum metric {
+    METRIC_ENTROPY_PER_BYTE,
+    METRIC_ENTROPY_OF_FILE,
+    METRIC_DELTA,
+    METRIC_CODING_RATIO,
+    METRIC_BIT_ENTROPY,
+    METRIC_NGRAM_ENTROPY,
+    METRIC_PCT_OF_REFERENCE,
+    METRIC_CONDITIONAL_ENTROPY,
+    METRIC_DELTA_ENTROPY,
+    METRIC_TRANSFORMED_ENTROPY,
+    METRIC_CLASSIFICATION,
+    METRIC_NORMALIZED_ENTROPY,
+    METRIC_HARTLEY_ENTROPY,
+    METRIC_MONTE_CARLO_PI,
+    METRIC_LZ_COMPLEXITY,
+    METRIC_BIT_STREAM_ENTROPY,
+    METRIC_CODEPOINT_ENTROPY,
+    METRIC_INDEX_OF_COINCIDENCE,
+    METRIC_ENTROPY_RATE,
+    METRIC_KL_DIVERGENCE,
+    METRIC_COUNT
+};
+
+struct metric_explanation {
+    const char *name;
+    const char *text;
+};
+
+// JSON Schema (draft 2020-12) of one --format ndjson line. Keep it in step with
+// print_ndjson when fields are added.
+static const char result_schema[] =
+    "{\n"
+    "  \"$schema\": \"https://json-schema.org/draft/2020-12/schema\",\n"
+    "  \"title\": \"entropy calculator ndjson result\",\n"
+    "  \"description\": \"One line of --format ndjson output: either a file's metrics or the error that stopped its analysis.\",\n"
+    "  \"type\": \"object\",\n"
+    "  \"oneOf\": [\n"
+    "    {\n"
+    "      \"required\": [\n"
+    "        \"filename\",\n"
+    "        \"error\",\n"
+    "        \"error_kind\"\n"
+    "      ],\n"
+    "      \"properties\": {\n"
+    "        \"filename\": {\n"
+    "          \"type\": \"string\",\n"
+    "          \"description\": \"The path as given, <stdin>, or archive!member with --archives.\"\n"
+    "        },\n"
+    "        \"error\": {\n"
+    "          \"type\": \"string\"\n"
+    "        },\n"
+    "        \"error_kind\": {\n"
+    "          \"enum\": [\n"
+    "            \"io\",\n"
+    "            \"empty\",\n"
+    "            \"range\",\n"
+    "            \"out_of_memory\",\n"
+    "            \"decompress\",\n"
+    "            \"archive\",\n"
+    "            \"alphabet\",\n"
+    "            \"device\",\n"
+    "            \"encoding\"\n"
+    "          ],\n"
+    "          \"description\": \"What went wrong, stable for scripts to match on; error is the message for people.\"\n"
+    "        },\n"
+    "        \"label\": {\n"
+    "          \"type\": \"string\",\n"
+    "          \"description\": \"Only with --label: the text given.\"\n"
+    "        },\n"
+    "        \"timestamp\": {\n"
+    "          \"type\": \"string\",\n"
+    "          \"format\": \"date-time\",\n"
+    "          \"description\": \"Only with --label: when the run started, in UTC (ISO 8601).\"\n"
+    "        }\n"
+    "      },\n"
+    "      \"additionalProperties\": false\n"
+    "    },\n"
+    "    {\n"
+    "      \"required\": [\n"
+    "        \"filename\",\n"
+    "        \"size_bytes\"\n"
+    "      ],\n"
+    "      \"not\": {\n"
+    "        \"required\": [\n"
+    "          \"error\"\n"
+    "        ]\n"
+    "      }\n"
+    "    }\n"
+    "  ],\n"
+    "  \"properties\": {\n"
+    "    \"filename\": {\n"
+    "      \"type\": \"string\",\n"
+    "      \"description\": \"The path as given, <stdin>, or archive!member with --archives.\"\n"
+    "    },\n"
+    "    \"size_bytes\": {\n"
+    "      \"type\": \"integer\",\n"
+    "      \"minimum\": 1,\n"
+    "      \"description\": \"Bytes analyzed (the range size with --offset/--length).\"\n"
+    "    },\n"
+    "    \"offset\": {\n"
+    "      \"type\": \"integer\",\n"
+    "      \"minimum\": 0,\n"
+    "      \"description\": \"Only with --offset or --length: where the range starts.\"\n"
+    "    },\n"
+    "    \"is_estimate\": {\n"
+    "      \"type\": \"boolean\",\n"
+    "      \"description\": \"Only with --sample: whether the metrics come from sampled blocks. Files no larger than the sample are read in full.\"\n"
+    "    },\n"
+    "    \"sampled_from_bytes\": {\n"
+    "      \"type\": \"integer\",\n"
+    "      \"minimum\": 0,\n"
+    "      \"description\": \"Only for estimates: the size of the whole file; size_bytes is the size of the sample.\"\n"
+    "    },\n"
+    "    \"sample_seed\": {\n"
+    "      \"type\": \"integer\",\n"
+    "      \"minimum\": 0,\n"
+    "      \"description\": \"Only for estimates: the --seed that repeats the sample.\"\n"
+    "    },\n"
+    "    \"byte_entropy\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Shannon entropy per byte, 0 to 8. In bits; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "    },\n"
+    "    \"bit_entropy\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Entropy of the set-bit count per byte, 0 to log2(9). In bits; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "    },\n"
+    "    \"entropy_of_file\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Entropy per byte times the bytes counted. In bits; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "    },\n"
+    "    \"delta_bytes\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Bytes an order-0 coder could save at best.\"\n"
+    "    },\n"
+    "    \"coding_ratio\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"8 / entropy per byte; null for constant files.\"\n"
+    "    },\n"
+    "    \"normalized_entropy\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Headline entropy over its maximum, 0 to 1.\"\n"
+    "    },\n"
+    "    \"detected_type\": {\n"
+    "      \"type\": [\n"
+    "        \"string\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Format recognized from the magic bytes, or null.\"\n"
+    "    },\n"
+    "    \"distinct_bytes\": {\n"
+    "      \"type\": \"integer\",\n"
+    "      \"minimum\": 1,\n"
+    "      \"maximum\": 256\n"
+    "    },\n"
+    "    \"hartley_entropy\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"log2 of distinct_bytes. In bits; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "    },\n"
+    "    \"index_of_coincidence\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Chance two bytes drawn without replacement are equal; null under 2 bytes.\"\n"
+    "    },\n"
+    "    \"collision_entropy\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Renyi entropy of order 2. In bits; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "    },\n"
+    "    \"ngram_entropy\": {\n"
+    "      \"type\": \"array\",\n"
+    "      \"description\": \"Only with --ngram.\",\n"
+    "      \"items\": {\n"
+    "        \"type\": \"object\",\n"
+    "        \"required\": [\n"
+    "          \"n\"\n"
+    "        ],\n"
+    "        \"properties\": {\n"
+    "          \"n\": {\n"
+    "            \"type\": \"integer\",\n"
+    "            \"minimum\": 1\n"
+    "          },\n"
+    "          \"entropy\": {\n"
+    "            \"type\": [\n"
+    "              \"number\",\n"
+    "              \"null\"\n"
+    "            ],\n"
+    "            \"description\": \"Entropy per byte of overlapping n-byte sequences. In bits; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "          }\n"
+    "        }\n"
+    "      }\n"
+    "    },\n"
+    "    \"conditional_entropy\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --conditional. In bits; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "    },\n"
+    "    \"delta_entropy\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --delta-entropy. In bits; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "    },\n"
+    "    \"pi_estimate\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --monte-carlo.\"\n"
+    "    },\n"
+    "    \"pi_error_pct\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --monte-carlo.\"\n"
+    "    },\n"
+    "    \"codepoint_entropy\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --codepoint. In bits per Unicode character; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "    },\n"
+    "    \"invalid_utf8_sequences\": {\n"
+    "      \"type\": \"integer\",\n"
+    "      \"minimum\": 0,\n"
+    "      \"description\": \"Only with --codepoint: invalid UTF-8 sequences, each counted as U+FFFD.\"\n"
+    "    },\n"
+    "    \"bit_stream_entropy\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --bit-stream. In bits per bit, 0 to 1; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "    },\n"
+    "    \"bit_position_entropy\": {\n"
+    "      \"type\": \"array\",\n"
+    "      \"description\": \"Only with --bit-stream: the entropy of bit 0 (least significant) to bit 7 of each byte, always in bits.\",\n"
+    "      \"items\": {\n"
+    "        \"type\": \"number\",\n"
+    "        \"minimum\": 0,\n"
+    "        \"maximum\": 1\n"
+    "      },\n"
+    "      \"minItems\": 8,\n"
+    "      \"maxItems\": 8\n"
+    "    },\n"
+    "    \"lz_complexity\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --lz. Normalized LZ76 phrase count of the first 1 MiB; null for files under 2 bytes.\"\n"
+    "    },\n"
+    "    \"pct_of_reference\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --relative-to.\"\n"
+    "    },\n"
+    "    \"entropy_rate_bits_per_sec\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --rate-bytes-per-sec.\"\n"
+    "    },\n"
+    "    \"kl_divergence\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --reference; null when infinite.\"\n"
+    "    },\n"
+    "    \"total_variation\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --reference.\"\n"
+    "    },\n"
+    "    \"transform\": {\n"
+    "      \"type\": \"string\",\n"
+    "      \"description\": \"Only with --transform: the transform applied.\"\n"
+    "    },\n"
+    "    \"transformed_entropy\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --transform. In bits; with --millibits the key gets a _millibits suffix and holds an integer.\"\n"
+    "    },\n"
+    "    \"classification\": {\n"
+    "      \"enum\": [\n"
+    "        \"text\",\n"
+    "        \"compressed\",\n"
+    "        \"encrypted\",\n"
+    "        \"structured\",\n"
+    "        \"unknown\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --classify.\"\n"
+    "    },\n"
+    "    \"chi_square\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --classify.\"\n"
+    "    },\n"
+    "    \"invalid_bytes\": {\n"
+    "      \"type\": \"integer\",\n"
+    "      \"minimum\": 0,\n"
+    "      \"description\": \"Only with --alphabet.\"\n"
+    "    },\n"
+    "    \"invalid_fraction\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --alphabet.\"\n"
+    "    },\n"
+    "    \"analysis_ms\": {\n"
+    "      \"type\": [\n"
+    "        \"number\",\n"
+    "        \"null\"\n"
+    "      ],\n"
+    "      \"description\": \"Only with --timings.\"\n"
+    "    },\n"
+    "    \"byte_counts\": {\n"
+    "      \"type\": \"array\",\n"
+    "      \"description\": \"Only with --include-histogram: [byte, count] pairs for non-zero counts.\",\n"
+    "      \"items\": {\n"
+    "        \"type\": \"array\",\n"
+    "        \"prefixItems\": [\n"
+    "          {\n"
+    "            \"type\": \"integer\",\n"
+    "            \"minimum\": 0,\n"
+    "            \"maximum\": 255\n"
+    "          },\n"
+    "          {\n"
+    "            \"type\": \"integer\",\n"
+    "            \"minimum\": 1\n"
+    "          }\n"
+    "        ],\n"
+    "        \"items\": false\n"
+    "      }\n"
+    "    },\n"
+    "    \"label\": {\n"
+    "      \"type\": \"string\",\n"
+    "      \"description\": \"Only with --label: the text given.\"\n"
+    "    },\n"
+    "    \"timestamp\": {\n"
+    "      \"type\": \"string\",\n"
+    "      \"format\": \"date-time\",\n"
+    "      \"description\": \"Only with --label: when the run started, in UTC (ISO 8601).\"\n"
+    "    }\n"
+    "  },\n"
+    "  \"patternProperties\": {\n"
+    "    \"_millibits$\": {\n"
+    "      \"type\": [\n"
+    "        \"integer\",\n"
+    "        \"null\"\n"
+    "      ]\n"
+    "    }\n"
+    "  }\n"
+    "}\n";
+
+static const struct metric_explanation explanations[METRIC_COUNT] = {
+    [METRIC_ENTROPY_PER_BYTE] = {"Entropy per byte",
+        "H = -sum(p * log2(p)) over the 256 byte frequencies. 0 bits means every byte is the same, 8 bits means all byte values are equally likely."},
+    [METRIC_ENTROPY_OF_FILE] = {"Entropy of file",
+        "H * size. The fewest bits an ideal coder that treats bytes independently needs to store the whole file."},
+    [METRIC_DELTA] = {"Delta",
+        "size - H * size / 8. How many bytes such a coder could save at best; 0 means the file is already incompressible this way."},
+    [METRIC_CODING_RATIO] = {"Best Theoretical Coding ratio",
+        "8 / H. Upper bound on the compression ratio (original / compressed) when each byte is coded on its own."},
+    [METRIC_BIT_ENTROPY] = {"Bit-level informational entropy",
+        "Entropy of the number of set bits per byte (0 to 8). Ranges from 0 to log2(9) = 3.169925 bits."},
+    [METRIC_NGRAM_ENTROPY] = {"N-gram entropy",
+        "Entropy of all overlapping n-byte sequences divided by n. Falls below the entropy per byte when bytes depend on their neighbours."},
+    [METRIC_PCT_OF_REFERENCE] = {"Percent of reference",
+        "100 * H / H(reference), using the same entropy measure for both files. 100% means the file is exactly as random as the reference."},
+    [METRIC_CONDITIONAL_ENTROPY] = {"Conditional entropy",
+        "H(X_n | X_n-1) from the 256x256 table of byte transitions, the last byte counting as the one before the first. Never exceeds the entropy per byte; a large gap means each byte is predictable from the one before it."},
+    [METRIC_DELTA_ENTROPY] = {"Delta entropy",
+        "Entropy of data[i] - data[i-1] (mod 256). Much lower than the entropy per byte for smooth data such as samples or counters, which delta coding compresses well."},
+    [METRIC_CLASSIFICATION] = {"Classification",
+        "A heuristic, not content detection. Text: at least 95% printable ASCII. Encrypted: entropy >= 7.9 and chi-square below 330.5, as uniform random data gives. Compressed: entropy >= 7.5. Structured: anything else. Unknown: under 256 bytes."},
+    [METRIC_NORMALIZED_ENTROPY] = {"Normalized entropy",
+        "H / H(max): entropy per byte over 8 bits, or bit-level entropy over log2(9) bits. Uniform random bytes reach 100% per byte but only about 80% bit-level, since set-bit counts are binomially distributed."},
+    [METRIC_HARTLEY_ENTROPY] = {"Hartley entropy",
+        "log2 of the number of distinct byte values present. The entropy per byte can only reach it when every present value is equally common."},
+    [METRIC_MONTE_CARLO_PI] = {"Monte Carlo value for Pi",
+        "Each 6 bytes form a point (24-bit X, 24-bit Y) in a square; 4 * the share inside the inscribed quarter circle approaches Pi for random data. Trailing bytes that do not fill a point are ignored."},
+    [METRIC_LZ_COMPLEXITY] = {"Lempel-Ziv complexity",
+        "c * log_k(n) / n, where c is the number of phrases in the LZ76 factorization of the first n bytes (at most 1 MiB) and k is the number of distinct byte values. Each phrase is the shortest run not seen before, so repetitive data needs few long phrases. Random data scores highest, approaching 1 for long inputs whatever its byte histogram; periodic data tends to 0."},
+    [METRIC_BIT_STREAM_ENTROPY] = {"Bit-stream entropy",
+        "Binary entropy -p*log2(p) - (1-p)*log2(1-p) of the share p of 1 bits among all bits, from 0 to 1 bit per bit. The bit-position values do the same for each bit of every byte alone, listed from bit 0 (least significant) to bit 7 in every output, so index i of the ndjson array is bit i; ASCII text, for example, always has bit 7 clear. This differs from the bit-level entropy of --bit, which is the entropy of how many bits are set in each byte."},
+    [METRIC_CODEPOINT_ENTROPY] = {"Codepoint entropy",
+        "Entropy of the Unicode characters of the UTF-8 decoded file, in bits per character. Up to log2(1,112,064) = 20.1 bits. A file of emoji has a high entropy per byte, since each character takes 4 varied bytes, but can have a low entropy per character."},
+    [METRIC_INDEX_OF_COINCIDENCE] = {"Index of coincidence",
+        "sum(n * (n - 1)) / (N * (N - 1)): the chance that two bytes picked at random are equal. About 1/256 = 0.0039 for uniform random data, 1 for a constant file. Collision (Renyi-2) entropy is -log2(sum(p^2)) and never exceeds the entropy per byte."},
+    [METRIC_ENTROPY_RATE] = {"Entropy rate",
+        "H * rate: the information carried per second by a stream of rate bytes per second, in bits per second. It uses the entropy per byte even with --bit."},
+    [METRIC_KL_DIVERGENCE] = {"KL divergence",
+        "sum(p * log2(p / q)) for the file's byte frequencies p and the reference q, in bits: the extra bits per byte a coder built for the reference would spend. Infinite when the file uses a byte the reference rules out. Total variation distance is sum(|p - q|) / 2, from 0 (identical) to 1 (no overlap)."},
+    [METRIC_TRANSFORMED_ENTROPY] = {"Entropy after transform",
+        "The same entropy measure applied to the transformed bytes. Byteswap and XOR with a single-byte key never change it; a drop after delta means neighbouring bytes are close in value."},
+};
+
+struct footnotes {
+    int enabled;
+    int count;
+    enum metric metrics[METRIC_COUNT];
 };
 
+static int hex_digit(char c) {
+    if (c >= '0' && c <= '9') {
+        return c - '0';
+    }
+    if (c >= 'a' && c <= 'f') {
+        return c - 'a' + 10;
+    }
+    if (c >= 'A' && c <= 'F') {
+        return c - 'A' + 10;
+    }
+    return -1;
+}
+
+// Parses "xor:HEX", "byteswap" or "delta". Returns 0 on success.
+int parse_transform(const char *spec, struct transform *transform) {
+    memset(transform, 0, sizeof(*transform));
+    transform->spec = spec;
+
+    if (strcmp(spec, "byteswap") == 0) {
+        transform->kind = TRANSFORM_BYTESWAP;
+        return 0;
+    }
+    if (strcmp(spec, "delta") == 0) {
+        transform->kind = TRANSFORM_DELTA;
+        return 0;
+    }
+    if (strncmp(spec, "xor:", 4) != 0) {
+        return -1;
+    }
+
+    const char *hex = spec + 4;
+    size_t digits = strlen(hex);
+    if (digits == 0 || digits % 2 != 0 || digits / 2 > MAX_XOR_KEY) {
+        return -1;
+    }
+    for (size_t i = 0; i < digits; i += 2) {
+        int high = hex_digit(hex[i]);
+        int low = hex_digit(hex[i + 1]);
+        if (high < 0 || low < 0) {
+            return -1;
+        }
+        transform->key[transform->key_length++] = (uint8_t)(high << 4 | low);
+    }
+    transform->kind = TRANSFORM_XOR;
+    return 0;
+}
+
+// Parses a floating point option value. Returns 0 on success.
+static int parse_double(const char *arg, double *value) {
+    char *end;
+    errno = 0;
+    *value = strtod(arg, &end);
+    return (*arg == '\0' || *end != '\0' || errno != 0 || isnan(*value)) ? -1 : 0;
+}
+
+// Parses a byte count, decimal or 0x-prefixed hex, with an optional K, M or G
+// suffix (powers of 1024). Returns 0 on success.
+static int parse_size(const char *arg, uint64_t *size) {
+    char *end;
+    int base = arg[0] == '0' && (arg[1] == 'x' || arg[1] == 'X') ? 16 : 10;
+    errno = 0;
+    unsigned long long value = strtoull(arg, &end, base);
+    if (*arg < '0' || *arg > '9' || errno != 0) {
+        return -1;
+    }
+
+    int shift = 0;
+    switch (*end) {
+        case 'k': case 'K': shift = 10; end++; break;
+        case 'm': case 'M': shift = 20; end++; break;
+        case 'g': case 'G': shift = 30; end++; break;
+    }
+    if (*end != '\0' || value > (UINT64_MAX >> shift)) {
+        return -1;
+    }
+    *size = (uint64_t)value << shift;
+    return 0;
+}
+
 static error_t parse_opt(int key, char *arg, struct argp_state *state) {
     struct arguments *arguments = state->input;
     switch (key) {
+        case 'v':
+            log_level = LOG_INFO;
+            break;
+        case 'q':
+            log_level = LOG_QUIET;
+            break;
         case 'b':
             arguments->bit_level = 1;
             break;
+        case OPT_EXPLAIN:
+            arguments->explain = 1;
+            break;
+        case OPT_NGRAM: {
+            char *end;
+            unsigned long n = strtoul(arg, &end, 10);
+            if (*arg == '\0' || *end != '\0' || n == 0) {
+                argp_error(state, "invalid n-gram size '%s'", arg);
+            }
+            if (arguments->ngram_count == MAX_NGRAM_SIZES) {
+                argp_error(state, "at most %d n-gram sizes can be given", MAX_NGRAM_SIZES);
+            }
+            arguments->ngram_sizes[arguments->ngram_count++] = n;
+            break;
+        }
+        case OPT_RELATIVE_TO:
+            arguments->relative_to = arg;
+            break;
+        case OPT_CONDITIONAL:
+            arguments->conditional = 1;
+            break;
+        case OPT_DELTA_ENTROPY:
+            arguments->delta_entropy = 1;
+            break;
+        case OPT_ALL_METRICS:
+            arguments->all_metrics = 1;
+            break;
+        case OPT_MONTE_CARLO:
+            arguments->monte_carlo = 1;
+            break;
+        case OPT_LZ:
+            arguments->lz = 1;
+            break;
+        case OPT_BIT_STREAM:
+            arguments->bit_stream = 1;
+            break;
+        case OPT_CODEPOINT:
+            if (arg && strcmp(arg, "strict") != 0 && strcmp(arg, "replace") != 0) {
+                argp_error(state, "invalid codepoint mode '%s', expected replace or strict", arg);
+            }
+            arguments->codepoint = 1;
+            arguments->codepoint_strict = arg && strcmp(arg, "strict") == 0;
+            break;
+        case OPT_TIMINGS:
+            arguments->timings = 1;
+            break;
+        case OPT_ALPHABET:
+            arguments->alphabet_file = arg;
+            break;
+        case OPT_REFERENCE:
+            arguments->reference = arg;
+            break;
+        case 'o':
+            arguments->output = arg;
+            break;
+        case OPT_INCLUDE_HISTOGRAM:
+            arguments->include_histogram = 1;
+            break;
+        case OPT_DIGRAM_HEATMAP:
+            arguments->digram_heatmap = 1;
+            break;
+        case OPT_CLASSIFY:
+            arguments->classify = 1;
+            break;
+        case OPT_THREADS: {
+            char *end;
+            long threads = strtol(arg, &end, 10);
+            if (*arg == '\0' || *end != '\0' || threads < 1 || threads > MAX_THREADS) {
+                argp_error(state, "invalid thread count '%s', expected 1 to %d", arg, MAX_THREADS);
+            }
+            arguments->threads = threads;
+            break;
+        }
+        case OPT_BUFFER_SIZE: {
+            char *end;
+            unsigned long size = strtoul(arg, &end, 10);
+            if (*arg == '\0' || *end != '\0' || size == 0 || size > MAX_BUFFER_SIZE) {
+                argp_error(state, "invalid buffer size '%s', expected 1 to %d bytes", arg, MAX_BUFFER_SIZE);
+            }
+            arguments->buffer_size = size;
+            break;
+        }
+        case OPT_HISTOGRAM_SVG:
+            arguments->histogram_svg = arg;
+            break;
+        case OPT_HISTOGRAM_BINS: {
+            char *end;
+            long bins = strtol(arg, &end, 10);
+            if (*arg == '\0' || *end != '\0' || bins < 1 || bins > 256 || 256 % bins != 0) {
+                argp_error(state, "invalid bin count '%s', expected a divisor of 256 (1, 2, 4, ... 256)", arg);
+            }
+            arguments->histogram_bins = bins;
+            break;
+        }
+        case OPT_HISTOGRAM_LOG:
+            arguments->histogram_log = 1;
+            break;
+        case OPT_FILES_FROM:
+            arguments->files_from = arg;
+            break;
+        case OPT_CACHE:
+            arguments->cache_path = arg;
+            break;
+        case OPT_SORT_INPUT:
+            arguments->sort_input = 1;
+            break;
+        case OPT_FAIL_FAST:
+            arguments->fail_fast = 1;
+            break;
+        case OPT_ARCHIVES:
+            arguments->archives = 1;
+            break;
+        case OPT_DECOMPRESS:
+            arguments->decompress = 1;
+            break;
+        case OPT_ALLOW_DEVICES:
+            arguments->allow_devices = 1;
+            break;
+        case OPT_WATCH:
+            arguments->watch = 1;
+            break;
+        case OPT_DRY_RUN:
+            arguments->dry_run = 1;
+            break;
+        case OPT_SORT:
+            if (strcmp(arg, "entropy") == 0) {
+                arguments->sort = SORT_ENTROPY;
+            } else if (strcmp(arg, "size") == 0) {
+                arguments->sort = SORT_SIZE;
+            } else if (strcmp(arg, "name") == 0) {
+                arguments->sort = SORT_NAME;
+            } else {
+                argp_error(state, "unknown sort key '%s'", arg);
+            }
+            break;
+        case OPT_REVERSE:
+            arguments->reverse = 1;
+            break;
+        case OPT_TOP:
+        case OPT_BOTTOM: {
+            char *end;
+            unsigned long n = strtoul(arg, &end, 10);
+            if (*arg < '0' || *arg > '9' || *end != '\0' || n == 0) {
+                argp_error(state, "invalid result count '%s'", arg);
+            }
+            if (key == OPT_TOP) {
+                arguments->top = n;
+            } else {
+                arguments->bottom = n;
+            }
+            break;
+        }
+        case OPT_NO_COLOR:
+            arguments->no_color = 1;
+            break;
+        case OPT_NO_PROGRESS:
+            arguments->no_progress = 1;
+            break;
+        case OPT_OFFSET:
+            if (parse_size(arg, &arguments->offset) != 0) {
+                argp_error(state, "invalid offset '%s'", arg);
+            }
+            break;
+        case OPT_LENGTH:
+            if (parse_size(arg, &arguments->length) != 0 || arguments->length == 0) {
+                argp_error(state, "invalid length '%s'", arg);
+            }
+            break;
+        case OPT_SAMPLE: {
+            char *end;
+            unsigned long n = strtoul(arg, &end, 10);
+            if (*arg < '0' || *arg > '9' || *end != '\0' || n == 0 || n > MAX_SAMPLE_BLOCKS) {
+                argp_error(state, "invalid sample '%s', expected 1 to %d blocks", arg, MAX_SAMPLE_BLOCKS);
+            }
+            arguments->sample_blocks = n;
+            break;
+        }
+        case OPT_SEED: {
+            char *end;
+            errno = 0;
+            arguments->seed = strtoull(arg, &end, 0);
+            if (*arg < '0' || *arg > '9' || *end != '\0' || errno) {
+                argp_error(state, "invalid seed '%s'", arg);
+            }
+            arguments->seed_set = 1;
+            break;
+        }
+        case OPT_MIN_SIZE:
+            if (parse_size(arg, &arguments->min_size) != 0) {
+                argp_error(state, "invalid size '%s'", arg);
+            }
+            break;
+        case OPT_MAX_SIZE:
+            if (parse_size(arg, &arguments->max_size) != 0) {
+                argp_error(state, "invalid size '%s'", arg);
+            }
+            break;
+        case OPT_ENTROPY_HISTOGRAM: {
+            long bins = DEFAULT_HISTOGRAM_BINS;
+            if (arg) {
+                char *end;
+                bins = strtol(arg, &end, 10);
+                if (*arg == '\0' || *end != '\0' || bins < 1 || bins > MAX_HISTOGRAM_BINS) {
+                    argp_error(state, "invalid bin count '%s', expected 1 to %d", arg, MAX_HISTOGRAM_BINS);
+                }
+            }
+            arguments->entropy_histogram_bins = bins;
+            break;
+        }
+        case OPT_CLUSTER: {
+            char *end;
+            long clusters = strtol(arg, &end, 10);
+            if (*arg == '\0' || *end != '\0' || clusters < 1 || clusters > MAX_CLUSTERS) {
+                argp_error(state, "invalid cluster count '%s', expected 1 to %d", arg, MAX_CLUSTERS);
+            }
+            arguments->clusters = clusters;
+            break;
+        }
+        case OPT_DISTRIBUTION_DIFF: {
+            long bins = DEFAULT_HISTOGRAM_BINS;
+            if (arg) {
+                char *end;
+                bins = strtol(arg, &end, 10);
+                if (*arg == '\0' || *end != '\0' || bins < 1 || bins > 256 || 256 % bins != 0) {
+                    argp_error(state, "invalid bin count '%s', expected a divisor of 256", arg);
+                }
+            }
+            arguments->distribution_diff_bins = bins;
+            break;
+        }
+        case 'f':
+            if (strcmp(arg, "text") == 0) {
+                arguments->format = FORMAT_TEXT;
+            } else if (strcmp(arg, "markdown") == 0) {
+                arguments->format = FORMAT_MARKDOWN;
+            } else if (strcmp(arg, "ndjson") == 0) {
+                arguments->format = FORMAT_NDJSON;
+            } else if (strcmp(arg, "html") == 0) {
+                arguments->format = FORMAT_HTML;
+            } else if (strcmp(arg, "compact") == 0) {
+                arguments->format = FORMAT_COMPACT;
+            } else {
+                argp_error(state, "unknown output format '%s'", arg);
+            }
+            break;
+        case OPT_MILLIBITS:
+            arguments->millibits = 1;
+            break;
+        case OPT_PRECISION: {
+            char *end;
+            long digits = strtol(arg, &end, 10);
+            if (*arg < '0' || *arg > '9' || *end != '\0' || digits > MAX_PRECISION) {
+                argp_error(state, "invalid precision '%s', expected 0 to %d digits", arg, MAX_PRECISION);
+            }
+            precision = digits;
+            break;
+        }
+        case OPT_TRANSFORM:
+            if (parse_transform(arg, &arguments->transform) != 0) {
+                argp_error(state, "invalid transform '%s', expected xor:HEX, byteswap or delta", arg);
+            }
+            break;
+        case OPT_THRESHOLD_MIN:
+            if (parse_double(arg, &arguments->threshold_min) != 0) {
+                argp_error(state, "invalid threshold '%s'", arg);
+            }
+            break;
+        case OPT_THRESHOLD_MAX:
+            if (parse_double(arg, &arguments->threshold_max) != 0) {
+                argp_error(state, "invalid threshold '%s'", arg);
+            }
+            break;
+        case OPT_PRINT_SCHEMA:
+            if (arg && strcmp(arg, "result") != 0) {
+                argp_error(state, "unknown schema '%s', expected result", arg);
+            }
+            fputs(result_schema, stdout);
+            exit(EXIT_SUCCESS);
+        case OPT_LABEL:
+            arguments->label = arg;
+            break;
+        case OPT_ENTROPY_ONLY:
+            arguments->entropy_only = 1;
+            break;
+        case OPT_RATE:
+            if (parse_double(arg, &arguments->rate) != 0 || !(arguments->rate > 0) || isinf(arguments->rate)) {
+                argp_error(state, "invalid rate '%s', expected a positive number of bytes per second", arg);
+            }
+            break;
         case ARGP_KEY_ARG:
             arguments->files = &state->argv[state->next - 1];
             state->next = state->argc;
             break;
         case ARGP_KEY_END:
-            if (state->argc < 2) {
+            if (!arguments->files && !arguments->files_from) {
                 argp_usage(state);
             }
+            if (arguments->entropy_histogram_bins && arguments->format != FORMAT_TEXT && arguments->format != FORMAT_COMPACT) {
+                argp_error(state, "--entropy-histogram is only available with --format text or compact");
+            }
+            if (arguments->clusters && arguments->format != FORMAT_TEXT && arguments->format != FORMAT_COMPACT) {
+                argp_error(state, "--cluster is only available with --format text or compact");
+            }
+            if (arguments->distribution_diff_bins && arguments->format != FORMAT_TEXT && arguments->format != FORMAT_COMPACT) {
+                argp_error(state, "--distribution-diff is only available with --format text or compact");
+            }
+            if (arguments->digram_heatmap && arguments->format != FORMAT_TEXT) {
+                argp_error(state, "--digram-heatmap is only available with --format text");
+            }
+            if (arguments->entropy_only && (arguments->format != FORMAT_TEXT || arguments->label || arguments->entropy_histogram_bins
+                    || arguments->clusters || arguments->distribution_diff_bins || arguments->histogram_svg || arguments->digram_heatmap)) {
+                argp_error(state, "--entropy-only cannot be combined with --format, --label or charts");
+            }
+            if (arguments->threshold_min > arguments->threshold_max) {
+                argp_error(state, "--threshold-min must not be greater than --threshold-max");
+            }
+            if (arguments->sample_blocks && (arguments->offset || arguments->length != UINT64_MAX || arguments->decompress || arguments->archives)) {
+                argp_error(state, "--sample cannot be combined with --offset, --length, --decompress or --archives");
+            }
+            if (arguments->seed_set && !arguments->sample_blocks) {
+                argp_error(state, "--seed only applies to --sample");
+            }
+            if (arguments->archives && (arguments->offset || arguments->length != UINT64_MAX)) {
+                argp_error(state, "--archives cannot be combined with --offset or --length");
+            }
+            if (arguments->dry_run && (arguments->format == FORMAT_HTML || arguments->watch)) {
+                argp_error(state, "--dry-run cannot be combined with --format html or --watch");
+            }
+            if (arguments->watch && arguments->output) {
+                argp_error(state, "--watch cannot be combined with --output");
+            }
+            if (arguments->reverse && arguments->sort == SORT_NONE) {
+                argp_error(state, "--reverse needs --sort");
+            }
+            if (arguments->top && arguments->bottom) {
+                argp_error(state, "--top and --bottom cannot be combined");
+            }
+            if ((arguments->top || arguments->bottom) && arguments->sort == SORT_NONE) {
+                arguments->sort = SORT_ENTROPY;
+                arguments->reverse = 1;
+            }
+            if (arguments->min_size > arguments->max_size) {
+                argp_error(state, "--min-size must not be greater than --max-size");
+            }
             break;
         default:
             return ARGP_ERR_UNKNOWN;
@@ -43,85 +1214,4003 @@ static error_t parse_opt(int key, char *arg, struct argp_state *state) {
 
 static struct argp argp = {options, parse_opt, args_doc, doc};
 
-void calculate_entropy(const char *filename, int bit_level);
+// Environment variables that set an option when it is not given on the
+// command line. They are parsed as if they came before the user's arguments,
+// so the same validation applies and command-line options still win.
+static const struct {
+    const char *variable;
+    const char *option;
+} environment_options[] = {
+    {"ENTROPY_FORMAT", "--format"},
+    {"ENTROPY_THREADS", "--threads"},
+    {"ENTROPY_BUFFER_SIZE", "--buffer-size"},
+    {"ENTROPY_MIN_SIZE", "--min-size"},
+    {"ENTROPY_MAX_SIZE", "--max-size"},
+};
+
+#define ENVIRONMENT_OPTION_COUNT (sizeof(environment_options) / sizeof(environment_options[0]))
+
+enum file_class {
+    CLASS_UNKNOWN,
+    CLASS_TEXT,
+    CLASS_STRUCTURED,
+    CLASS_COMPRESSED,
+    CLASS_ENCRYPTED,
+};
+
+static const char *file_class_names[] = {
+    [CLASS_UNKNOWN] = "unknown",
+    [CLASS_TEXT] = "text",
+    [CLASS_STRUCTURED] = "structured",
+    [CLASS_COMPRESSED] = "compressed",
+    [CLASS_ENCRYPTED] = "encrypted",
+};
+
+// Why a file could not be analyzed, for callers that need more than the message.
+enum error_kind {
+    ERROR_NONE,
+    ERROR_IO,               // Opening or reading failed
+    ERROR_EMPTY,            // No bytes, so entropy is undefined
+    ERROR_RANGE,            // --offset/--length past the end of the input
+    ERROR_MEMORY,
+    ERROR_DECOMPRESS,       // Corrupt, truncated or unsupported compressed input
+    ERROR_ARCHIVE,          // Corrupt or unsupported archive or archive member
+    ERROR_ALPHABET,         // No bytes from the --alphabet
+    ERROR_DEVICE,           // A device given without --allow-devices
+    ERROR_ENCODING,         // Invalid UTF-8 with --codepoint=strict
+};
+
+static const char *error_kind_names[] = {
+    [ERROR_NONE] = "none",
+    [ERROR_IO] = "io",
+    [ERROR_EMPTY] = "empty",
+    [ERROR_RANGE] = "range",
+    [ERROR_MEMORY] = "out_of_memory",
+    [ERROR_DECOMPRESS] = "decompress",
+    [ERROR_ARCHIVE] = "archive",
+    [ERROR_ALPHABET] = "alphabet",
+    [ERROR_DEVICE] = "device",
+    [ERROR_ENCODING] = "encoding",
+};
+
+struct file_analysis {
+    const char *filename;
+    char *owned_filename;   // Allocated "archive!entry" name, freed with the results
+    enum error_kind error_kind;
+    char error[256];        // The message for error_kind, empty when the analysis succeeded
+    uint64_t counts[256];
+    uint64_t size;
+    uint64_t symbols;       // Bytes counted towards entropy; less than size with --alphabet
+    uint64_t invalid_bytes;
+    uint8_t *data;
+    size_t data_length;
+    int data_mapped;        // data points into an mmap of the file rather than the heap
+    uint64_t offset;        // Where the analyzed range starts
+    int ranged;             // Only part of the file was analyzed (--offset or --length)
+    int is_estimate;        // Only --sample blocks were counted, so the metrics are estimates
+    uint64_t sampled_from;  // The file size when is_estimate
+    uint64_t seed;          // The --seed that placed the sample blocks
+    uint8_t magic[MAGIC_LENGTH]; // The first bytes of the file, for detect_file_type
+    size_t magic_length;
+
+    // Derived by compute_metrics; NAN marks a metric that is not available.
+    double byte_entropy;
+    double bit_entropy;
+    double normalized_entropy; // Entropy of the selected measure as a fraction of its maximum
+    int distinct_bytes;
+    double hartley_entropy;
+    double index_of_coincidence;
+    double collision_entropy;
+    double ngram_entropy[MAX_NGRAM_SIZES];
+    double conditional_entropy;
+    double delta_entropy;
+    double pi_estimate;
+    double pi_error_pct;
+    size_t lz_phrases;
+    double lz_complexity;
+    double bit_stream_entropy;
+    double codepoint_entropy;
+    uint64_t characters;    // UTF-8 characters decoded for --codepoint
+    uint64_t distinct_characters;
+    uint64_t invalid_sequences; // Invalid UTF-8 counted as U+FFFD
+    double bit_position_entropy[8]; // Indexed by bit, 0 being the least significant
+    uint64_t *digram_cells; // Allocated for --digram-heatmap and freed with the results
+    double pct_of_reference;
+    double entropy_rate;
+    double kl_divergence;
+    double total_variation;
+    double transformed_entropy;
+    double analysis_ms;
+    double chi_square;
+    enum file_class classification;
+    const char *detected_type; // NULL when no signature matched
+};
+
+// How analyze_file reads a file. Start from ANALYSIS_OPTIONS_DEFAULT and set
+// what differs, so new fields keep a sensible value.
+struct analysis_options {
+    int keep_data;          // Keep the raw bytes, for metrics that depend on byte order
+    int threads;            // Workers for large regular files read without a range
+    size_t buffer_size;     // Bytes per read
+    int show_progress;      // Draw a progress bar on stderr for large files
+    int decompress;         // Measure gzip and xz inputs after decompression; the range then counts decompressed bytes
+    uint64_t offset;        // First byte to count
+    uint64_t length;        // Bytes to count, UINT64_MAX for the rest of the file
+    int allow_devices;      // Read block and character devices instead of refusing them
+    unsigned long sample_blocks; // Estimate from this many blocks of regular files larger than the sample, 0 reads everything
+    uint64_t seed;          // Where the sample blocks go
+};
+
+#define ANALYSIS_OPTIONS_DEFAULT {0, 1, DEFAULT_BUFFER_SIZE, 0, 0, 0, UINT64_MAX, 0, 0, 0}
+
+int analyze_file(const char *filename, const struct analysis_options *options, struct file_analysis *analysis);
+void free_analysis(struct file_analysis *analysis);
+static double primary_entropy(const struct file_analysis *analysis, const struct arguments *arguments);
+static void print_markdown_header(const struct arguments *arguments);
+static void print_html_header(const struct arguments *arguments);
+static void format_timestamp(char *timestamp, size_t size);
+void render_histogram_svg(FILE *out, const struct file_analysis *analysis, int bins, int log_scale);
+static void print_html_footer(const struct file_analysis *results, size_t count, const struct arguments *arguments);
+int calculate_entropy(const char *filename, const struct arguments *arguments, double reference_entropy, struct file_analysis *analysis);
+int load_reference_distribution(const char *spec, double distribution[256]);
+double calculate_kl_divergence(const uint64_t counts[256], uint64_t total, const double reference[256]);
+double calculate_total_variation(const uint64_t counts[256], uint64_t total, const double reference[256]);
+
+// A growable array of results; pointers into it are only valid until the next add_result.
+struct result_list {
+    struct file_analysis *items;
+    size_t count;
+    size_t capacity;
+};
+
+struct file_analysis *add_result(struct result_list *results);
+int is_archive(const char *filename);
+int analyze_archive(const char *filename, const struct arguments *arguments, double reference_entropy, struct result_list *results);
+void print_result(const struct file_analysis *analysis, const struct arguments *arguments);
+void sort_results(const struct file_analysis **results, size_t count, const struct arguments *arguments);
+void print_entropy_histogram(const struct file_analysis *results, size_t count, int bins, int color);
+void print_distribution_diff(const struct file_analysis *left, const struct file_analysis *right, int bins);
+void print_entropy_clusters(const struct file_analysis *results, size_t count, int clusters, int bit_level);
+void print_digram_heatmap(FILE *out, const uint64_t *cells, int color);
+// A growable, NULL-terminated list of allocated paths.
+struct file_list {
+    char **paths;
+    size_t count;
+    size_t capacity;
+};
+
+int analyze_files(const struct file_list *files, const struct arguments *arguments, double reference_entropy);
+void print_dry_run(const struct file_list *files, const struct arguments *arguments);
+int start_watch(const struct file_list *files, int *watches);
+int wait_for_change(int fd, const struct file_list *files, const int *watches);
+int expand_file_arguments(struct file_list *list, char **args);
+int read_file_list(struct file_list *list, const char *path);
+void sort_file_list(struct file_list *list);
+void free_file_list(struct file_list *list);
+int within_size_limits(const char *filename, const struct arguments *arguments);
+int within_size_range(const char *filename, uint64_t size, const struct arguments *arguments);
+
+// One cached file: its identity when counted, the counting options, and the counts.
+struct cache_entry {
+    char *path;
+    uint64_t size;
+    int64_t mtime_ns;
+    uint64_t offset;
+    uint64_t length;
+    int decompress;
+    uint8_t magic[MAGIC_LENGTH];
+    size_t magic_length;
+    uint64_t counts[256];
+};
+
+// Byte counts of earlier runs, indexed by path with an open-addressing hash table.
+struct analysis_cache {
+    struct cache_entry *entries;
+    size_t count;
+    size_t capacity;
+    size_t *slots;          // Entry index + 1, or 0 for an empty slot
+    size_t slot_count;
+    int dirty;
+};
+
+int load_cache(struct analysis_cache *cache, const char *path);
+int save_cache(const struct analysis_cache *cache, const char *path);
+void free_cache(struct analysis_cache *cache);
+
+// Exit statuses. A file that cannot be analyzed outranks a threshold violation.
+enum exit_status {
+    EXIT_OK = 0,
+    EXIT_FILE_ERROR = 1,
+    EXIT_THRESHOLD = 2,
+};
 
 int main(int argc, char *argv[]) {
     struct arguments arguments;
     arguments.bit_level = 0;
+    arguments.all_metrics = 0;
+    arguments.explain = 0;
+    arguments.ngram_count = 0;
+    arguments.relative_to = NULL;
+    arguments.conditional = 0;
+    arguments.delta_entropy = 0;
+    arguments.monte_carlo = 0;
+    arguments.lz = 0;
+    arguments.bit_stream = 0;
+    arguments.codepoint = 0;
+    arguments.codepoint_strict = 0;
+    arguments.format = FORMAT_TEXT;
+    arguments.entropy_only = 0;
+    arguments.entropy_only_names = 0;
+    arguments.run_summary = 0;
+    arguments.millibits = 0;
+    arguments.transform.kind = TRANSFORM_NONE;
+    arguments.threshold_min = -INFINITY;
+    arguments.threshold_max = INFINITY;
+    arguments.rate = 0;
+    arguments.timings = 0;
+    arguments.alphabet_file = NULL;
+    arguments.reference = NULL;
+    arguments.output = NULL;
+    arguments.label = NULL;
+    arguments.timestamp[0] = '\0';
+    arguments.include_histogram = 0;
+    arguments.digram_heatmap = 0;
+    arguments.histogram_svg = NULL;
+    arguments.histogram_bins = 256;
+    arguments.histogram_log = 0;
+    arguments.classify = 0;
+    arguments.threads = 0;
+    arguments.buffer_size = DEFAULT_BUFFER_SIZE;
+    arguments.entropy_histogram_bins = 0;
+    arguments.distribution_diff_bins = 0;
+    arguments.clusters = 0;
+    arguments.sort = SORT_NONE;
+    arguments.reverse = 0;
+    arguments.top = 0;
+    arguments.bottom = 0;
+    arguments.no_color = 0;
+    arguments.color = 0;
+    arguments.no_progress = 0;
+    arguments.progress = 0;
+    arguments.offset = 0;
+    arguments.length = UINT64_MAX;
+    arguments.sample_blocks = 0;
+    arguments.seed = 0;
+    arguments.seed_set = 0;
+    arguments.min_size = 0;
+    arguments.max_size = UINT64_MAX;
     arguments.files = NULL;
+    arguments.files_from = NULL;
+    arguments.watch = 0;
+    arguments.dry_run = 0;
+    arguments.archives = 0;
+    arguments.decompress = 0;
+    arguments.allow_devices = 0;
+    arguments.fail_fast = 0;
+    arguments.sort_input = 0;
+    arguments.cache_path = NULL;
+    arguments.cache = NULL;
+
+    char *environmentArgv[argc + 2 * ENVIRONMENT_OPTION_COUNT + 1];
+    int environmentArgc = 0;
+    environmentArgv[environmentArgc++] = argv[0];
+    for (size_t i = 0; i < ENVIRONMENT_OPTION_COUNT; i++) {
+        char *value = getenv(environment_options[i].variable);
+        if (value && value[0]) {
+            environmentArgv[environmentArgc++] = (char *)environment_options[i].option;
+            environmentArgv[environmentArgc++] = value;
+        }
+    }
+    for (int i = 1; i <= argc; i++) {
+        environmentArgv[environmentArgc++] = argv[i];
+    }
 
-    argp_parse(&argp, argc, argv, 0, 0, &arguments);
+    argp_parse(&argp, environmentArgc - 1, environmentArgv, 0, 0, &arguments);
 
-    for (int i = 0; arguments.files[i]; i++) {
-        calculate_entropy(arguments.files[i], arguments.bit_level);
+    if (arguments.threads == 0) {
+        long cpus = sysconf(_SC_NPROCESSORS_ONLN);
+        arguments.threads = cpus < 1 ? 1 : cpus > MAX_THREADS ? MAX_THREADS : cpus;
     }
 
-    return 0;
-}
+    // The alphabet and reference files are read whole, without a progress bar.
+    struct analysis_options sideOptions = ANALYSIS_OPTIONS_DEFAULT;
+    sideOptions.threads = arguments.threads;
+    sideOptions.buffer_size = arguments.buffer_size;
 
-void calculate_entropy(const char *filename, int bit_level) {
-    FILE *file = fopen(filename, "rb");
-    if (!file) {
-        fprintf(stderr, "Error opening file: %s\n", filename);
-        return;
+    if (arguments.alphabet_file) {
+        struct file_analysis alphabet;
+        if (analyze_file(arguments.alphabet_file, &sideOptions, &alphabet) != 0) {
+            fprintf(stderr, "%s\n", alphabet.error);
+            return EXIT_FILE_ERROR;
+        }
+        for (int i = 0; i < 256; i++) {
+            arguments.alphabet[i] = alphabet.counts[i] > 0;
+        }
+        free_analysis(&alphabet);
     }
 
-    uint32_t counts[256] = {0};
-    uint8_t buffer[BUFFER_SIZE];
-    size_t bytesRead;
-    uint64_t totalBits = 0;
+    if (arguments.reference && load_reference_distribution(arguments.reference, arguments.reference_distribution) != 0) {
+        return EXIT_FILE_ERROR;
+    }
 
-    while ((bytesRead = fread(buffer, 1, BUFFER_SIZE, file)) > 0) {
-        for (size_t i = 0; i < bytesRead; i++) {
-            counts[buffer[i]]++;
-            totalBits += 8;
+    // A missing cache starts empty. Anything else that cannot be loaded is left
+    // alone rather than overwritten, in case PATH was a mistake.
+    struct analysis_cache cache = {0};
+    if (arguments.cache_path) {
+        if (load_cache(&cache, arguments.cache_path) != 0 && errno != ENOENT) {
+            fprintf(stderr, "Error reading cache: %s: %s\n", arguments.cache_path, errno ? strerror(errno) : "not a cache written by this program");
+            free_cache(&cache);
+            return EXIT_FILE_ERROR;
         }
+        arguments.cache = &cache;
     }
 
-    fclose(file);
+    double referenceEntropy = NAN;
+    if (arguments.relative_to) {
+        struct file_analysis reference;
+        if (analyze_file(arguments.relative_to, &sideOptions, &reference) != 0) {
+            fprintf(stderr, "%s\n", reference.error);
+            return EXIT_FILE_ERROR;
+        }
+        referenceEntropy = primary_entropy(&reference, &arguments);
+        free_analysis(&reference);
+    }
 
-    if (bit_level) {
-        uint32_t bitCounts[9] = {0}; // To count bytes with 0 to 8 bits set
-        for (int i = 0; i < 256; i++) {
-            int bitSum = 0;
-            for (int j = 0; j < 8; j++) {
-                bitSum += (i >> j) & 1;
-            }
-            bitCounts[bitSum] += counts[i];
+    // Every printer writes to stdout, so redirecting it keeps file output
+    // byte-identical to what would have been printed.
+    if (arguments.output && !freopen(arguments.output, "w", stdout)) {
+        fprintf(stderr, "Error opening output file: %s: %s\n", arguments.output, strerror(errno));
+        return EXIT_FILE_ERROR;
+    }
+
+    const char *noColor = getenv("NO_COLOR");
+    arguments.color = arguments.format == FORMAT_TEXT && !arguments.no_color
+        && !(noColor && noColor[0]) && isatty(STDOUT_FILENO);
+    arguments.progress = !arguments.no_progress && log_level != LOG_QUIET && isatty(STDERR_FILENO);
+    if (!arguments.seed_set) {
+        struct timespec now;
+        clock_gettime(CLOCK_REALTIME, &now);
+        arguments.seed = (uint64_t)now.tv_sec * 1000000000 + now.tv_nsec;
+    }
+
+    struct file_list files = {0};
+    if (arguments.files && expand_file_arguments(&files, arguments.files) != 0) {
+        fprintf(stderr, "Out of memory\n");
+        return EXIT_FILE_ERROR;
+    }
+    if (arguments.files_from && read_file_list(&files, arguments.files_from) != 0) {
+        fprintf(stderr, "Error reading file list: %s: %s\n", arguments.files_from, strerror(errno));
+        return EXIT_FILE_ERROR;
+    }
+    if (arguments.sort_input) {
+        sort_file_list(&files);
+    }
+    size_t fileCount = files.count;
+    arguments.entropy_only_names = fileCount > 1 || (fileCount == 1 && arguments.archives && is_archive(files.paths[0]));
+    if (arguments.dry_run) {
+        print_dry_run(&files, &arguments);
+        free_file_list(&files);
+        free_cache(&cache);
+        if (fflush(stdout) != 0) {
+            fprintf(stderr, "Error writing output: %s\n", strerror(errno));
+            return EXIT_FILE_ERROR;
         }
+        return EXIT_OK;
+    }
+    if (arguments.histogram_svg && fileCount != 1) {
+        fprintf(stderr, "--histogram-svg needs exactly one file, got %zu\n", fileCount);
+        return EXIT_FILE_ERROR;
+    }
 
-        double bitEntropy = 0.0;
-        for (int i = 0; i < 9; i++) {
-            if (bitCounts[i] > 0) {
-                double prob = (double)bitCounts[i] / (totalBits / 8);
-                bitEntropy -= prob * log2(prob);
+    int watchFd = -1;
+    int *watches = NULL;
+    if (arguments.watch) {
+        for (size_t i = 0; i < fileCount; i++) {
+            if (strcmp(files.paths[i], "-") == 0) {
+                fprintf(stderr, "--watch cannot watch stdin\n");
+                free_file_list(&files);
+                free_cache(&cache);
+                return EXIT_FILE_ERROR;
             }
         }
+        watches = malloc((fileCount + 1) * sizeof(int));
+        if (!watches || (watchFd = start_watch(&files, watches)) < 0) {
+            fprintf(stderr, "Error watching files: %s\n", strerror(errno));
+            free(watches);
+            free_file_list(&files);
+            free_cache(&cache);
+            return EXIT_FILE_ERROR;
+        }
+    }
 
-        printf("\n--- File: %s ---\n", filename);
-        printf("---------------------------------------\n");
-        printf("Bit-level informational entropy: %.6f bits\n", bitEntropy);
-        printf("---------------------------------------\n");
-    } else {
-        double entropy = 0.0;
-        for (int i = 0; i < 256; i++) {
-            if (counts[i] > 0) {
-                double prob = (double)counts[i] / (totalBits / 8);
-                entropy -= prob * log2(prob);
-            }
+    if (arguments.label) {
+        format_timestamp(arguments.timestamp, sizeof(arguments.timestamp));
+    }
+    arguments.run_summary = !arguments.entropy_only;
+    int status = analyze_files(&files, &arguments, referenceEntropy);
+    arguments.run_summary = 0;
+    if (cache.dirty && save_cache(&cache, arguments.cache_path) != 0) {
+        log_warn("Warning: could not write cache %s: %s", arguments.cache_path, strerror(errno));
+    }
+    while (arguments.watch) {
+        fflush(stdout);
+        if (wait_for_change(watchFd, &files, watches) != 0) {
+            fprintf(stderr, "Error watching files: %s\n", strerror(errno));
+            status = EXIT_FILE_ERROR;
+            break;
+        }
+        if (isatty(STDOUT_FILENO)) {
+            printf("\033[H\033[2J");
+        }
+        if (arguments.label) {
+            format_timestamp(arguments.timestamp, sizeof(arguments.timestamp));
         }
+        status = analyze_files(&files, &arguments, referenceEntropy);
+        if (cache.dirty && save_cache(&cache, arguments.cache_path) != 0) {
+            log_warn("Warning: could not write cache %s: %s", arguments.cache_path, strerror(errno));
+        }
+    }
+    if (watchFd >= 0) {
+        close(watchFd);
+    }
+    free(watches);
+    free_file_list(&files);
+    free_cache(&cache);
 
-        double entropyPerByte = entropy / 8;
-        double entropyOfFile = entropy * totalBits / 8;
+    if (fflush(stdout) != 0) {
+        fprintf(stderr, "Error writing output: %s\n", strerror(errno));
+        return EXIT_FILE_ERROR;
+    }
+    return status;
+}
 
-        printf("\n--- File: %s ---\n", filename);
-        printf("---------------------------------------\n");
-        printf("Entropy per byte              : %.6f bits (%.6f bytes)\n", entropy, entropyPerByte);
-        printf("Entropy of file               : %.6f bits (%.6f bytes)\n", entropyOfFile, entropyOfFile / 8);
-        printf("Size of file                  : %llu bytes\n", totalBits / 8);
-        printf("Delta                         : %.6f bytes (compressible theoretically)\n", totalBits / 8 - entropyOfFile / 8);
-        printf("Best Theoretical Coding ratio : %.6f\n", 8 / entropy);
-        printf("---------------------------------------\n");
+static const char *footnote(struct footnotes *notes, enum metric m) {
+    static char marker[16];
+    if (!notes->enabled) {
+        return "";
     }
-    printf("\n");
-}
\ No newline at end of file
+
+    int index = 0;
+    while (index < notes->count && notes->metrics[index] != m) {
+        index++;
+    }
+    if (index == notes->count) {
+        notes->metrics[notes->count++] = m;
+    }
+    snprintf(marker, sizeof(marker), " [%d]", index + 1);
+    return marker;
+}
+
+static void print_footnotes(const struct footnotes *notes) {
+    for (int i = 0; i < notes->count; i++) {
+        const struct metric_explanation *e = &explanations[notes->metrics[i]];
+        printf("[%d] %s: %s\n", i + 1, e->name, e->text);
+    }
+}
+
+struct ngram_context {
+    const uint8_t *data;
+    size_t n;
+};
+
+static int compare_ngrams(const void *a, const void *b, void *context) {
+    const struct ngram_context *ctx = context;
+    return memcmp(ctx->data + *(const size_t *)a, ctx->data + *(const size_t *)b, ctx->n);
+}
+
+// Entropy of the overlapping n-byte sequences, normalized to bits per byte.
+// N-grams are counted by sorting their offsets so memory stays linear in the
+// file size instead of growing with 256^n.
+double calculate_ngram_entropy(const uint8_t *data, size_t length, size_t n) {
+    if (n == 0 || length < n) {
+        return 0.0;
+    }
+
+    size_t total = length - n + 1;
+    size_t *offsets = malloc(total * sizeof(size_t));
+    if (!offsets) {
+        return NAN;
+    }
+    for (size_t i = 0; i < total; i++) {
+        offsets[i] = i;
+    }
+
+    struct ngram_context context = {data, n};
+    qsort_r(offsets, total, sizeof(size_t), compare_ngrams, &context);
+
+    double entropy = 0.0;
+    size_t run = 1;
+    for (size_t i = 1; i <= total; i++) {
+        if (i < total && memcmp(data + offsets[i - 1], data + offsets[i], n) == 0) {
+            run++;
+            continue;
+        }
+        double prob = (double)run / total;
+        entropy -= prob * log2(prob);
+        run = 1;
+    }
+
+    free(offsets);
+    return entropy / n;
+}
+
+// H(X_n | X_n-1): the entropy of a byte once the byte before it is known.
+// The last byte is taken to precede the first, so both sides of the pairs
+// have the file's own byte counts and the result never exceeds its entropy.
+double calculate_conditional_entropy(const uint8_t *data, size_t length) {
+    if (length < 2) {
+        return 0.0;
+    }
+
+    uint64_t *transitions = calloc(256 * 256, sizeof(uint64_t));
+    if (!transitions) {
+        return NAN;
+    }
+    for (size_t i = 1; i < length; i++) {
+        transitions[data[i - 1] * 256 + data[i]]++;
+    }
+    transitions[data[length - 1] * 256 + data[0]]++;
+
+    size_t total = length;
+    double entropy = 0.0;
+    for (int prev = 0; prev < 256; prev++) {
+        const uint64_t *row = &transitions[prev * 256];
+        uint64_t rowTotal = 0;
+        for (int next = 0; next < 256; next++) {
+            rowTotal += row[next];
+        }
+        for (int next = 0; next < 256; next++) {
+            if (row[next] > 0) {
+                double joint = (double)row[next] / total;
+                entropy -= joint * log2((double)row[next] / rowTotal);
+            }
+        }
+    }
+
+    free(transitions);
+    return entropy;
+}
+
+double calculate_byte_entropy(const uint64_t counts[256], uint64_t total) {
+    double entropy = 0.0;
+    for (int i = 0; i < 256; i++) {
+        if (counts[i] > 0) {
+            double prob = (double)counts[i] / total;
+            entropy -= prob * log2(prob);
+        }
+    }
+    return entropy;
+}
+
+double calculate_bit_entropy(const uint64_t counts[256], uint64_t total) {
+    uint64_t bitCounts[9] = {0}; // To count bytes with 0 to 8 bits set
+    for (int i = 0; i < 256; i++) {
+        int bitSum = 0;
+        for (int j = 0; j < 8; j++) {
+            bitSum += (i >> j) & 1;
+        }
+        bitCounts[bitSum] += counts[i];
+    }
+
+    double bitEntropy = 0.0;
+    for (int i = 0; i < 9; i++) {
+        if (bitCounts[i] > 0) {
+            double prob = (double)bitCounts[i] / total;
+            bitEntropy -= prob * log2(prob);
+        }
+    }
+    return bitEntropy;
+}
+
+static double binary_entropy(double p) {
+    if (p <= 0 || p >= 1) {
+        return 0.0;
+    }
+    return -p * log2(p) - (1 - p) * log2(1 - p);
+}
+
+// Treats the bytes as one stream of 8 * total bits and returns the entropy of
+// a single bit, from 0 to 1. positions receives the same for each bit of the
+// byte on its own, bit 0 being the least significant.
+double calculate_bit_stream_entropy(const uint64_t counts[256], uint64_t total, double positions[8]) {
+    uint64_t ones[8] = {0};
+    uint64_t allOnes = 0;
+    for (int i = 0; i < 256; i++) {
+        for (int j = 0; j < 8; j++) {
+            if ((i >> j) & 1) {
+                ones[j] += counts[i];
+            }
+        }
+    }
+    for (int j = 0; j < 8; j++) {
+        positions[j] = binary_entropy((double)ones[j] / total);
+        allOnes += ones[j];
+    }
+    return binary_entropy((double)allOnes / (8.0 * total));
+}
+
+// Writes the transformed bytes to out, which must hold length bytes.
+// byteswap swaps each pair of bytes (16-bit endianness); an odd trailing byte is kept.
+// delta replaces each byte with its wrapping difference from the previous one.
+void apply_transform(const struct transform *transform, const uint8_t *data, uint8_t *out, size_t length) {
+    switch (transform->kind) {
+        case TRANSFORM_XOR:
+            for (size_t i = 0; i < length; i++) {
+                out[i] = data[i] ^ transform->key[i % transform->key_length];
+            }
+            break;
+        case TRANSFORM_BYTESWAP:
+            for (size_t i = 0; i + 1 < length; i += 2) {
+                out[i] = data[i + 1];
+                out[i + 1] = data[i];
+            }
+            if (length % 2) {
+                out[length - 1] = data[length - 1];
+            }
+            break;
+        case TRANSFORM_DELTA:
+            for (size_t i = 0; i < length; i++) {
+                out[i] = (uint8_t)(data[i] - (i ? data[i - 1] : 0));
+            }
+            break;
+        case TRANSFORM_NONE:
+            memcpy(out, data, length);
+            break;
+    }
+}
+
+// Entropy of the wrapping differences between consecutive bytes.
+double calculate_delta_entropy(const uint8_t *data, size_t length) {
+    if (length < 2) {
+        return 0.0;
+    }
+
+    uint64_t differences[256] = {0};
+    for (size_t i = 1; i < length; i++) {
+        differences[(uint8_t)(data[i] - data[i - 1])]++;
+    }
+    return calculate_byte_entropy(differences, length - 1);
+}
+
+// Counts each pair of consecutive bytes into a DIGRAM_CELLS x DIGRAM_CELLS
+// matrix, row by the first byte and column by the second, so each cell sums
+// the pairs of a block of (256 / DIGRAM_CELLS)^2 byte values. Returns NULL
+// when out of memory.
+uint64_t *calculate_digram_cells(const uint8_t *data, size_t length) {
+    const int span = 256 / DIGRAM_CELLS;
+    uint64_t *cells = calloc(DIGRAM_CELLS * DIGRAM_CELLS, sizeof(*cells));
+    if (!cells) {
+        return NULL;
+    }
+    for (size_t i = 1; i < length; i++) {
+        cells[(data[i - 1] / span) * DIGRAM_CELLS + data[i] / span]++;
+    }
+    return cells;
+}
+
+// Decodes one UTF-8 character at data[*position] and advances past it.
+// Overlong forms, surrogates, values above U+10FFFF and cut-off sequences are
+// invalid: they return -1 and skip a single byte, so decoding resumes at the
+// next possible character.
+static int32_t decode_utf8(const uint8_t *data, size_t length, size_t *position) {
+    static const uint32_t minimum[4] = {0, 0x80, 0x800, 0x10000};
+    uint8_t lead = data[*position];
+    int extra = lead < 0x80 ? 0 : lead >= 0xc2 && lead <= 0xdf ? 1 : lead >= 0xe0 && lead <= 0xef ? 2 : lead >= 0xf0 && lead <= 0xf4 ? 3 : -1;
+
+    if (extra == 0) {
+        (*position)++;
+        return lead;
+    }
+    if (extra < 0 || length - *position <= (size_t)extra) {
+        (*position)++;
+        return -1;
+    }
+    uint32_t codepoint = lead & (0x3f >> extra);
+    for (int i = 1; i <= extra; i++) {
+        uint8_t next = data[*position + i];
+        if ((next & 0xc0) != 0x80) {
+            (*position)++;
+            return -1;
+        }
+        codepoint = (codepoint << 6) | (next & 0x3f);
+    }
+    if (codepoint < minimum[extra] || codepoint > 0x10ffff || (codepoint >= 0xd800 && codepoint <= 0xdfff)) {
+        (*position)++;
+        return -1;
+    }
+    *position += extra + 1;
+    return codepoint;
+}
+
+// Shannon entropy of the characters of UTF-8 text, in bits per character.
+// Invalid sequences count as U+FFFD; their number goes to *invalid. Counts live
+// in an open-addressing table, since the alphabet can be up to 1.1 million
+// characters. Returns NAN when out of memory.
+double calculate_codepoint_entropy(const uint8_t *data, size_t length, uint64_t *characters, uint64_t *distinct, uint64_t *invalid) {
+    const uint32_t empty = UINT32_MAX;
+    size_t capacity = 1024;
+    uint32_t *keys = malloc(capacity * sizeof(*keys));
+    uint64_t *counts = calloc(capacity, sizeof(*counts));
+    *characters = 0;
+    *distinct = 0;
+    *invalid = 0;
+
+    if (!keys || !counts) {
+        free(keys);
+        free(counts);
+        return NAN;
+    }
+    memset(keys, 0xff, capacity * sizeof(*keys));
+
+    size_t position = 0;
+    while (position < length) {
+        int32_t decoded = decode_utf8(data, length, &position);
+        if (decoded < 0) {
+            (*invalid)++;
+            decoded = 0xfffd;
+        }
+        (*characters)++;
+
+        size_t slot = ((uint32_t)decoded * 2654435761u) & (capacity - 1);
+        while (keys[slot] != empty && keys[slot] != (uint32_t)decoded) {
+            slot = (slot + 1) & (capacity - 1);
+        }
+        if (keys[slot] == empty) {
+            keys[slot] = decoded;
+            (*distinct)++;
+        }
+        counts[slot]++;
+
+        // Double the table at half load, moving every character to its new slot
+        if (*distinct * 2 > capacity) {
+            size_t grownCapacity = capacity * 2;
+            uint32_t *grownKeys = malloc(grownCapacity * sizeof(*grownKeys));
+            uint64_t *grownCounts = calloc(grownCapacity, sizeof(*grownCounts));
+            if (!grownKeys || !grownCounts) {
+                free(grownKeys);
+                free(grownCounts);
+                free(keys);
+                free(counts);
+                return NAN;
+            }
+            memset(grownKeys, 0xff, grownCapacity * sizeof(*grownKeys));
+            for (size_t i = 0; i < capacity; i++) {
+                if (keys[i] == empty) {
+                    continue;
+                }
+                size_t to = (keys[i] * 2654435761u) & (grownCapacity - 1);
+                while (grownKeys[to] != empty) {
+                    to = (to + 1) & (grownCapacity - 1);
+                }
+                grownKeys[to] = keys[i];
+                grownCounts[to] = counts[i];
+            }
+            free(keys);
+            free(counts);
+            keys = grownKeys;
+            counts = grownCounts;
+            capacity = grownCapacity;
+        }
+    }
+
+    double entropy = 0.0;
+    for (size_t i = 0; i < capacity; i++) {
+        if (counts[i] > 0) {
+            double prob = (double)counts[i] / *characters;
+            entropy -= prob * log2(prob);
+        }
+    }
+    free(keys);
+    free(counts);
+    return entropy;
+}
+
+// Estimates Pi like ent: consecutive 6-byte groups give a 24-bit X and Y, and
+// 4 * (points inside the quarter circle) / points approaches Pi for random
+// data. Returns NAN when there is not a single full point.
+double calculate_monte_carlo_pi(const uint8_t *data, size_t length) {
+    const double radius = 256.0 * 256.0 * 256.0 - 1;
+    uint64_t points = length / 6;
+    uint64_t inside = 0;
+
+    if (points == 0) {
+        return NAN;
+    }
+    for (uint64_t i = 0; i < points; i++) {
+        const uint8_t *p = data + i * 6;
+        double x = (p[0] << 16) | (p[1] << 8) | p[2];
+        double y = (p[3] << 16) | (p[4] << 8) | p[5];
+        inside += x * x + y * y <= radius * radius;
+    }
+    return 4.0 * inside / points;
+}
+
+// Counts the phrases of the Lempel-Ziv 76 factorization: each phrase is the
+// shortest run starting where the last one ended that does not occur earlier
+// in the data (the earlier copy may overlap the phrase). A suffix automaton of
+// the data read so far tells whether the current phrase still occurs. Returns
+// 0 when out of memory.
+size_t calculate_lz_complexity(const uint8_t *data, size_t length) {
+    struct lz_state {
+        uint32_t len;
+        int32_t link;
+        uint32_t firstEdge;
+    };
+    struct lz_edge {
+        uint32_t target;
+        uint32_t next;
+        uint8_t symbol;
+    };
+    const uint32_t noEdge = UINT32_MAX;
+    struct lz_state *states = malloc((2 * length + 1) * sizeof(*states));
+    struct lz_edge *edges = malloc((3 * length + 4) * sizeof(*edges));
+    if (states == NULL || edges == NULL) {
+        free(states);
+        free(edges);
+        return 0;
+    }
+
+    uint32_t stateCount = 1;
+    uint32_t edgeCount = 0;
+    uint32_t last = 0;
+    states[0] = (struct lz_state){0, -1, noEdge};
+
+    // The edge leaving a state on a symbol, or noEdge
+#define LZ_FIND(state, c, edge) \
+    for ((edge) = states[state].firstEdge; (edge) != noEdge && edges[edge].symbol != (c); (edge) = edges[edge].next)
+#define LZ_ADD(state, c, to) \
+    do { \
+        edges[edgeCount] = (struct lz_edge){(to), states[state].firstEdge, (c)}; \
+        states[state].firstEdge = edgeCount++; \
+    } while (0)
+
+    size_t phrases = 0;
+    uint32_t match = 0;
+    uint32_t matchLength = 0;
+    for (size_t j = 0; j < length; j++) {
+        uint8_t c = data[j];
+        uint32_t edge;
+        LZ_FIND(match, c, edge);
+        int extends = edge != noEdge;
+        if (extends) {
+            match = edges[edge].target;
+            matchLength++;
+        }
+
+        // Append c to the automaton
+        uint32_t current = stateCount++;
+        states[current] = (struct lz_state){states[last].len + 1, 0, noEdge};
+        int32_t p = last;
+        for (; p != -1; p = states[p].link) {
+            LZ_FIND(p, c, edge);
+            if (edge != noEdge) {
+                break;
+            }
+            LZ_ADD(p, c, current);
+        }
+        if (p != -1) {
+            uint32_t q = edges[edge].target;
+            if (states[p].len + 1 == states[q].len) {
+                states[current].link = q;
+            } else {
+                uint32_t clone = stateCount++;
+                states[clone] = (struct lz_state){states[p].len + 1, states[q].link, noEdge};
+                for (uint32_t e = states[q].firstEdge; e != noEdge; e = edges[e].next) {
+                    LZ_ADD(clone, edges[e].symbol, edges[e].target);
+                }
+                for (; p != -1; p = states[p].link) {
+                    LZ_FIND(p, c, edge);
+                    if (edge == noEdge || edges[edge].target != q) {
+                        break;
+                    }
+                    edges[edge].target = clone;
+                }
+                states[q].link = clone;
+                states[current].link = clone;
+            }
+        }
+        last = current;
+
+        if (extends) {
+            // A clone may have taken over the shorter strings of the matched state
+            while (states[match].link != -1 && states[states[match].link].len >= matchLength) {
+                match = states[match].link;
+            }
+        } else {
+            phrases++;
+            match = 0;
+            matchLength = 0;
+        }
+    }
+#undef LZ_FIND
+#undef LZ_ADD
+
+    free(states);
+    free(edges);
+    return phrases + (matchLength > 0);
+}
+
+static void close_input(FILE *file) {
+    if (file != stdin) {
+        fclose(file);
+    }
+}
+
+// Kullback-Leibler divergence of the byte frequencies from a reference
+// distribution, in bits. Infinite when a byte occurs that the reference gives
+// probability 0.
+double calculate_kl_divergence(const uint64_t counts[256], uint64_t total, const double reference[256]) {
+    double divergence = 0.0;
+    for (int i = 0; i < 256; i++) {
+        if (counts[i] == 0) {
+            continue;
+        }
+        if (reference[i] <= 0) {
+            return INFINITY;
+        }
+        double prob = (double)counts[i] / total;
+        divergence += prob * log2(prob / reference[i]);
+    }
+    return divergence > 0 ? divergence : 0.0; // Rounding can leave a tiny negative sum
+}
+
+// Half the L1 distance between the byte frequencies and a reference distribution.
+double calculate_total_variation(const uint64_t counts[256], uint64_t total, const double reference[256]) {
+    double distance = 0.0;
+    for (int i = 0; i < 256; i++) {
+        distance += fabs((double)counts[i] / total - reference[i]);
+    }
+    return distance / 2;
+}
+
+// Fills distribution with 1/256 for "uniform", or with the JSON array of 256
+// non-negative probabilities in the file at spec. A sum a little off 1 (within
+// 1%) is renormalized. Reports problems on stderr and returns -1.
+int load_reference_distribution(const char *spec, double distribution[256]) {
+    if (strcmp(spec, "uniform") == 0) {
+        for (int i = 0; i < 256; i++) {
+            distribution[i] = 1.0 / 256;
+        }
+        return 0;
+    }
+
+    FILE *file = fopen(spec, "r");
+    if (!file) {
+        fprintf(stderr, "Error opening reference distribution: %s: %s\n", spec, strerror(errno));
+        return -1;
+    }
+    char *text = NULL;
+    size_t capacity = 0;
+    ssize_t length = getdelim(&text, &capacity, '\0', file);
+    fclose(file);
+    if (length < 0) {
+        fprintf(stderr, "Error reading reference distribution: %s\n", spec);
+        free(text);
+        return -1;
+    }
+
+    const char *problem = NULL;
+    int count = 0;
+    double sum = 0.0;
+    char *p = text + strspn(text, " \t\r\n");
+    if (*p++ != '[') {
+        problem = "expected a JSON array of 256 numbers";
+    }
+    while (!problem) {
+        p += strspn(p, " \t\r\n");
+        if (*p == ']' && count == 0) {
+            problem = "expected exactly 256 entries";
+            break;
+        }
+        char *end;
+        double value = strtod(p, &end);
+        if (end == p || !isfinite(value) || value < 0) {
+            problem = "entries must be non-negative numbers";
+            break;
+        }
+        if (count == 256) {
+            problem = "expected exactly 256 entries";
+            break;
+        }
+        distribution[count++] = value;
+        sum += value;
+        p = end + strspn(end, " \t\r\n");
+        if (*p == ']') {
+            p++;
+            break;
+        }
+        if (*p++ != ',') {
+            problem = "expected a JSON array of 256 numbers";
+        }
+    }
+    if (!problem && p[strspn(p, " \t\r\n")] != '\0') {
+        problem = "unexpected text after the array";
+    }
+    if (!problem && count != 256) {
+        problem = "expected exactly 256 entries";
+    }
+    if (!problem && fabs(sum - 1.0) > 0.01) {
+        problem = "the probabilities must sum to 1";
+    }
+    free(text);
+    if (problem) {
+        fprintf(stderr, "Error: invalid reference distribution %s: %s\n", spec, problem);
+        return -1;
+    }
+
+    for (int i = 0; i < 256; i++) {
+        distribution[i] /= sum;
+    }
+    return 0;
+}
+
+// The probability that two bytes drawn without replacement are equal, or NAN
+// for fewer than 2 bytes.
+double calculate_index_of_coincidence(const uint64_t counts[256], uint64_t total) {
+    if (total < 2) {
+        return NAN;
+    }
+
+    double matches = 0.0;
+    for (int i = 0; i < 256; i++) {
+        matches += (double)counts[i] * (counts[i] - 1.0);
+    }
+    return matches / ((double)total * (total - 1.0));
+}
+
+// Renyi entropy of order 2: -log2 of the probability that two bytes drawn with
+// replacement are equal.
+double calculate_collision_entropy(const uint64_t counts[256], uint64_t total) {
+    double sumOfSquares = 0.0;
+    for (int i = 0; i < 256; i++) {
+        double prob = (double)counts[i] / total;
+        sumOfSquares += prob * prob;
+    }
+    return sumOfSquares >= 1.0 ? 0.0 : -log2(sumOfSquares); // Avoids -0 for constant data
+}
+
+// Pearson's chi-square statistic of the byte counts against a uniform distribution.
+double calculate_chi_square(const uint64_t counts[256], uint64_t total) {
+    double expected = total / 256.0;
+    double chiSquare = 0.0;
+    for (int i = 0; i < 256; i++) {
+        double difference = counts[i] - expected;
+        chiSquare += difference * difference / expected;
+    }
+    return chiSquare;
+}
+
+enum file_class classify_file(const struct file_analysis *analysis) {
+    if (analysis->symbols < CLASSIFY_MIN_BYTES) {
+        return CLASS_UNKNOWN;
+    }
+
+    uint64_t printable = analysis->counts['\t'] + analysis->counts['\n'] + analysis->counts['\r'];
+    for (int i = 0x20; i < 0x7f; i++) {
+        printable += analysis->counts[i];
+    }
+    if (printable >= CLASSIFY_TEXT_PRINTABLE * analysis->symbols) {
+        return CLASS_TEXT;
+    }
+    if (analysis->byte_entropy >= CLASSIFY_ENCRYPTED_ENTROPY && analysis->chi_square < CLASSIFY_ENCRYPTED_CHI_SQUARE) {
+        return CLASS_ENCRYPTED;
+    }
+    if (analysis->byte_entropy >= CLASSIFY_COMPRESSED_ENTROPY) {
+        return CLASS_COMPRESSED;
+    }
+    return CLASS_STRUCTURED;
+}
+
+// Signatures at the start of common formats. Checked in order, so longer
+// signatures come before shorter ones they share a prefix with.
+static const struct {
+    const char *name;
+    const char *magic;
+    size_t length;
+} file_signatures[] = {
+    {"PNG image", "\x89PNG\r\n\x1a\n", 8},
+    {"JPEG image", "\xff\xd8\xff", 3},
+    {"GIF image", "GIF8", 4},
+    {"PDF document", "%PDF-", 5},
+    {"ZIP archive", "PK\x03\x04", 4},
+    {"gzip data", "\x1f\x8b", 2},
+    {"bzip2 data", "BZh", 3},
+    {"xz data", "\xfd" "7zXZ\x00", 6},
+    {"Zstandard data", "\x28\xb5\x2f\xfd", 4},
+    {"7-Zip archive", "7z\xbc\xaf\x27\x1c", 6},
+    {"RAR archive", "Rar!\x1a\x07", 6},
+    {"SQLite database", "SQLite format 3\x00", 16},
+    {"ELF executable", "\x7f" "ELF", 4},
+    {"Mach-O executable", "\xcf\xfa\xed\xfe", 4},
+    {"PE/DOS executable", "MZ", 2},
+};
+
+// Guesses the format from the leading magic bytes. A heuristic, not content
+// detection: it trusts the signature and never looks past it. Returns NULL
+// when nothing matches.
+const char *detect_file_type(const struct file_analysis *analysis) {
+    for (size_t i = 0; i < sizeof(file_signatures) / sizeof(file_signatures[0]); i++) {
+        if (analysis->magic_length >= file_signatures[i].length
+            && memcmp(analysis->magic, file_signatures[i].magic, file_signatures[i].length) == 0) {
+            return file_signatures[i].name;
+        }
+    }
+    return NULL;
+}
+
+// A byte-based progress bar on stderr, redrawn only when the percentage changes.
+struct progress {
+    const char *filename;
+    uint64_t total;
+    int percent;
+};
+
+static void update_progress(struct progress *progress, uint64_t done) {
+    int percent = done >= progress->total ? 100 : (int)(done * 100 / progress->total);
+    if (percent == progress->percent) {
+        return;
+    }
+    progress->percent = percent;
+
+    int filled = percent * PROGRESS_WIDTH / 100;
+    fprintf(stderr, "\r%s [%.*s%*s] %3d%%", progress->filename, filled, "##############################", PROGRESS_WIDTH - filled, "", percent);
+    fflush(stderr);
+}
+
+static void finish_progress(const struct progress *progress) {
+    if (progress->percent >= 0) {
+        fprintf(stderr, "\r\033[K");
+        fflush(stderr);
+    }
+}
+
+struct chunk_count {
+    const uint8_t *start;
+    size_t length;
+    uint64_t counts[256];
+    struct progress *progress; // Only on the first chunk; chunks are equal, so it stands in for all
+    int chunks;
+};
+
+static void *count_chunk(void *arg) {
+    struct chunk_count *chunk = arg;
+    for (size_t i = 0; i < chunk->length; i++) {
+        chunk->counts[chunk->start[i]]++;
+        if (chunk->progress && (i & 0xFFFFF) == 0) {
+            update_progress(chunk->progress, (uint64_t)i * chunk->chunks);
+        }
+    }
+    return NULL;
+}
+
+// Counts a large regular file by memory-mapping it and giving each thread one
+// chunk, then summing the per-chunk counts. Returns -1 without touching the
+// analysis when the file is too small or cannot be mapped, so the caller can
+// fall back to reading it.
+static int analyze_file_parallel(FILE *file, int keep_data, int threads, struct progress *progress, struct file_analysis *analysis) {
+    struct stat info;
+    if (threads < 2 || fstat(fileno(file), &info) != 0 || !S_ISREG(info.st_mode) || info.st_size < PARALLEL_MIN_BYTES) {
+        return -1;
+    }
+
+    size_t length = info.st_size;
+    uint8_t *mapping = mmap(NULL, length, PROT_READ, MAP_PRIVATE, fileno(file), 0);
+    if (mapping == MAP_FAILED) {
+        return -1;
+    }
+
+    struct chunk_count *chunks = calloc(threads, sizeof(struct chunk_count));
+    pthread_t *workers = calloc(threads, sizeof(pthread_t));
+    int *started = calloc(threads, sizeof(int));
+    if (!chunks || !workers || !started) {
+        free(chunks);
+        free(workers);
+        free(started);
+        munmap(mapping, length);
+        return -1;
+    }
+
+    size_t chunkLength = length / threads;
+    for (int t = 0; t < threads; t++) {
+        chunks[t].start = mapping + t * chunkLength;
+        chunks[t].length = t == threads - 1 ? length - t * chunkLength : chunkLength;
+        chunks[t].progress = t == 0 ? progress : NULL;
+        chunks[t].chunks = threads;
+        started[t] = pthread_create(&workers[t], NULL, count_chunk, &chunks[t]) == 0;
+        if (!started[t]) {
+            count_chunk(&chunks[t]);
+        }
+    }
+    for (int t = 0; t < threads; t++) {
+        if (started[t]) {
+            pthread_join(workers[t], NULL);
+        }
+        for (int i = 0; i < 256; i++) {
+            analysis->counts[i] += chunks[t].counts[i];
+        }
+    }
+    analysis->size = length;
+    analysis->magic_length = length < MAGIC_LENGTH ? length : MAGIC_LENGTH;
+    memcpy(analysis->magic, mapping, analysis->magic_length);
+
+    if (keep_data) {
+        analysis->data = mapping;
+        analysis->data_length = length;
+        analysis->data_mapped = 1;
+    } else {
+        munmap(mapping, length);
+    }
+
+    free(chunks);
+    free(workers);
+    free(started);
+    return 0;
+}
+
+// Skips the first offset bytes, seeking when possible and reading otherwise.
+static int skip_input(FILE *file, uint64_t offset) {
+    if (fseeko(file, offset, SEEK_SET) == 0) {
+        return 0;
+    }
+
+    char discard[4096];
+    while (offset > 0) {
+        size_t bytesRead = fread(discard, 1, offset < sizeof(discard) ? offset : sizeof(discard), file);
+        if (bytesRead == 0) {
+            return ferror(file) ? -1 : 0;
+        }
+        offset -= bytesRead;
+    }
+    return 0;
+}
+
+// Records why the analysis failed; error[0] then marks the result as failed.
+static void set_error(struct file_analysis *analysis, enum error_kind kind, const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    vsnprintf(analysis->error, sizeof(analysis->error), format, args);
+    va_end(args);
+    analysis->error_kind = kind;
+}
+
+enum compression {
+    COMPRESSION_NONE,
+    COMPRESSION_GZIP,
+    COMPRESSION_XZ,
+};
+
+// A stdio read stream that decodes a compressed source, so the read loop in
+// analyze_file does not need to know about compression.
+struct decoder {
+    FILE *source;
+    enum compression kind;
+    uint8_t prefix[6];      // Bytes read while detecting the format, replayed first
+    size_t prefix_length;
+    size_t prefix_used;
+    uint8_t input[DEFAULT_BUFFER_SIZE];
+    z_stream gzip;
+    lzma_stream xz;
+    int finished;
+    char error[128];        // Why the stream could not be decoded, for the per-file error
+};
+
+static size_t read_source(struct decoder *decoder, uint8_t *buffer, size_t size) {
+    size_t replayed = 0;
+    while (decoder->prefix_used < decoder->prefix_length && replayed < size) {
+        buffer[replayed++] = decoder->prefix[decoder->prefix_used++];
+    }
+    return replayed + fread(buffer + replayed, 1, size - replayed, decoder->source);
+}
+
+static ssize_t decode_gzip(struct decoder *decoder, char *out, size_t size) {
+    z_stream *stream = &decoder->gzip;
+    stream->next_out = (Bytef *)out;
+    stream->avail_out = size;
+    while (stream->avail_out > 0 && !decoder->finished) {
+        if (stream->avail_in == 0) {
+            stream->next_in = decoder->input;
+            stream->avail_in = read_source(decoder, decoder->input, sizeof(decoder->input));
+            if (stream->avail_in == 0) {
+                snprintf(decoder->error, sizeof(decoder->error), "%s",
+                    ferror(decoder->source) ? strerror(errno) : "the gzip stream is truncated");
+                return -1;
+            }
+        }
+        int result = inflate(stream, Z_NO_FLUSH);
+        if (result == Z_STREAM_END) {
+            // Concatenated gzip members decode as one stream, as gzip -d does.
+            if (stream->avail_in == 0) {
+                stream->next_in = decoder->input;
+                stream->avail_in = read_source(decoder, decoder->input, sizeof(decoder->input));
+            }
+            if (stream->avail_in == 0) {
+                decoder->finished = 1;
+            } else {
+                inflateReset(stream);
+            }
+        } else if (result != Z_OK && result != Z_BUF_ERROR) {
+            snprintf(decoder->error, sizeof(decoder->error), "corrupt gzip data (%s)", stream->msg ? stream->msg : "inflate failed");
+            return -1;
+        }
+    }
+    return size - stream->avail_out;
+}
+
+static ssize_t decode_xz(struct decoder *decoder, char *out, size_t size) {
+    lzma_stream *stream = &decoder->xz;
+    stream->next_out = (uint8_t *)out;
+    stream->avail_out = size;
+    while (stream->avail_out > 0 && !decoder->finished) {
+        lzma_action action = LZMA_RUN;
+        if (stream->avail_in == 0) {
+            stream->next_in = decoder->input;
+            stream->avail_in = read_source(decoder, decoder->input, sizeof(decoder->input));
+            if (stream->avail_in == 0) {
+                if (ferror(decoder->source)) {
+                    snprintf(decoder->error, sizeof(decoder->error), "%s", strerror(errno));
+                    return -1;
+                }
+                action = LZMA_FINISH;
+            }
+        }
+        lzma_ret result = lzma_code(stream, action);
+        if (result == LZMA_STREAM_END) {
+            decoder->finished = 1;
+        } else if (result == LZMA_BUF_ERROR && action == LZMA_FINISH) {
+            snprintf(decoder->error, sizeof(decoder->error), "the xz stream is truncated");
+            return -1;
+        } else if (result != LZMA_OK) {
+            snprintf(decoder->error, sizeof(decoder->error), result == LZMA_MEM_ERROR ? "out of memory decoding xz data" : "corrupt xz data");
+            return -1;
+        }
+    }
+    return size - stream->avail_out;
+}
+
+static ssize_t read_decoder(void *cookie, char *out, size_t size) {
+    struct decoder *decoder = cookie;
+    ssize_t produced;
+    if (decoder->kind == COMPRESSION_GZIP) {
+        produced = decode_gzip(decoder, out, size);
+    } else if (decoder->kind == COMPRESSION_XZ) {
+        produced = decode_xz(decoder, out, size);
+    } else {
+        produced = read_source(decoder, (uint8_t *)out, size);
+        if (produced == 0 && ferror(decoder->source)) {
+            snprintf(decoder->error, sizeof(decoder->error), "%s", strerror(errno));
+            produced = -1;
+        }
+    }
+    if (produced < 0) {
+        errno = EIO;
+    }
+    return produced;
+}
+
+// Releases the gzip or xz state; the source file is left open.
+static void end_decoder(struct decoder *decoder) {
+    if (decoder->kind == COMPRESSION_GZIP) {
+        inflateEnd(&decoder->gzip);
+    } else if (decoder->kind == COMPRESSION_XZ) {
+        lzma_end(&decoder->xz);
+    }
+}
+
+static int close_decoder(void *cookie) {
+    struct decoder *decoder = cookie;
+    end_decoder(decoder);
+    close_input(decoder->source);
+    free(decoder);
+    return 0;
+}
+
+// Detects gzip and xz from their magic bytes and returns a stream of the
+// decompressed contents, with *decoder set so read errors can be explained.
+// Other inputs come back unchanged when they can be rewound, and through a
+// pass-through decoder otherwise (stdin). Returns NULL with error set on failure.
+static FILE *open_decompressed(FILE *file, struct decoder **decoder, char *error, size_t error_size) {
+    static const uint8_t gzipMagic[] = {0x1f, 0x8b};
+    static const uint8_t xzMagic[] = {0xfd, '7', 'z', 'X', 'Z', 0x00};
+    static const uint8_t zstdMagic[] = {0x28, 0xb5, 0x2f, 0xfd};
+
+    struct decoder *state = calloc(1, sizeof(*state));
+    if (!state) {
+        snprintf(error, error_size, "out of memory");
+        return NULL;
+    }
+    state->source = file;
+    state->prefix_length = fread(state->prefix, 1, sizeof(state->prefix), file);
+
+    if (state->prefix_length >= sizeof(gzipMagic) && memcmp(state->prefix, gzipMagic, sizeof(gzipMagic)) == 0) {
+        state->kind = COMPRESSION_GZIP;
+        if (inflateInit2(&state->gzip, 16 + MAX_WBITS) != Z_OK) {
+            snprintf(error, error_size, "cannot start the gzip decoder");
+            free(state);
+            return NULL;
+        }
+    } else if (state->prefix_length >= sizeof(xzMagic) && memcmp(state->prefix, xzMagic, sizeof(xzMagic)) == 0) {
+        state->kind = COMPRESSION_XZ;
+        lzma_stream initial = LZMA_STREAM_INIT;
+        state->xz = initial;
+        if (lzma_stream_decoder(&state->xz, UINT64_MAX, LZMA_CONCATENATED) != LZMA_OK) {
+            snprintf(error, error_size, "cannot start the xz decoder");
+            free(state);
+            return NULL;
+        }
+    } else if (state->prefix_length >= sizeof(zstdMagic) && memcmp(state->prefix, zstdMagic, sizeof(zstdMagic)) == 0) {
+        snprintf(error, error_size, "zstd input is not supported by this build");
+        free(state);
+        return NULL;
+    } else if (fseeko(file, 0, SEEK_SET) == 0) {
+        free(state);
+        return file;
+    }
+
+    cookie_io_functions_t functions = {read_decoder, NULL, NULL, close_decoder};
+    FILE *stream = fopencookie(state, "rb", functions);
+    if (!stream) {
+        snprintf(error, error_size, "%s", strerror(errno));
+        // The caller still owns file and closes it.
+        end_decoder(state);
+        free(state);
+        return NULL;
+    }
+    *decoder = state;
+    return stream;
+}
+
+// splitmix64: small, fast and good enough to place sample blocks; the same
+// seed always gives the same sequence.
+static uint64_t next_random(uint64_t *state) {
+    uint64_t z = (*state += 0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9;
+    z = (z ^ (z >> 27)) * 0x94d049bb133111eb;
+    return z ^ (z >> 31);
+}
+
+// Counts sample_blocks blocks of SAMPLE_BLOCK_SIZE bytes instead of the whole
+// file: the file is cut into that many equal stretches and one block is read
+// at a random position in each, so the sample spans the file. The file must
+// be regular and longer than the sample.
+static int sample_file(FILE *file, uint64_t file_size, const struct analysis_options *options, struct file_analysis *analysis) {
+    uint64_t stretch = file_size / options->sample_blocks;
+    uint64_t state = options->seed;
+    uint8_t buffer[SAMPLE_BLOCK_SIZE];
+
+    if (options->keep_data) {
+        analysis->data = malloc((size_t)options->sample_blocks * SAMPLE_BLOCK_SIZE);
+        if (!analysis->data) {
+            set_error(analysis, ERROR_MEMORY, "Out of memory reading file: %s", analysis->filename);
+            return -1;
+        }
+    }
+
+    // The magic bytes still come from the start of the file
+    if (fseeko(file, 0, SEEK_SET) != 0) {
+        set_error(analysis, ERROR_IO, "Error reading file: %s: %s", analysis->filename, strerror(errno));
+        free_analysis(analysis);
+        return -1;
+    }
+    analysis->magic_length = fread(analysis->magic, 1, MAGIC_LENGTH, file);
+
+    for (unsigned long i = 0; i < options->sample_blocks; i++) {
+        uint64_t start = i * stretch + next_random(&state) % (stretch - SAMPLE_BLOCK_SIZE + 1);
+        if (fseeko(file, start, SEEK_SET) != 0 || fread(buffer, 1, SAMPLE_BLOCK_SIZE, file) != SAMPLE_BLOCK_SIZE) {
+            set_error(analysis, ERROR_IO, "Error reading file: %s: %s", analysis->filename,
+                ferror(file) ? strerror(errno) : "file shrank while sampling");
+            free_analysis(analysis);
+            return -1;
+        }
+        for (size_t j = 0; j < SAMPLE_BLOCK_SIZE; j++) {
+            analysis->counts[buffer[j]]++;
+        }
+        if (options->keep_data) {
+            memcpy(analysis->data + analysis->data_length, buffer, SAMPLE_BLOCK_SIZE);
+            analysis->data_length += SAMPLE_BLOCK_SIZE;
+        }
+    }
+
+    analysis->size = (uint64_t)options->sample_blocks * SAMPLE_BLOCK_SIZE;
+    analysis->symbols = analysis->size;
+    analysis->is_estimate = 1;
+    analysis->sampled_from = file_size;
+    analysis->seed = options->seed;
+    return 0;
+}
+
+// Reads the file once, counting byte values, as described by options. A
+// filename of "-" reads stdin.
+int analyze_file(const char *filename, const struct analysis_options *options, struct file_analysis *analysis) {
+    memset(analysis, 0, sizeof(*analysis));
+    analysis->filename = filename;
+
+    int fromStdin = strcmp(filename, "-") == 0;
+    if (fromStdin) {
+        analysis->filename = "<stdin>";
+    }
+
+    // Checked before opening, since opening some devices already has an effect.
+    struct stat info;
+    if (!fromStdin && !options->allow_devices && stat(filename, &info) == 0 && (S_ISBLK(info.st_mode) || S_ISCHR(info.st_mode))) {
+        set_error(analysis, ERROR_DEVICE, "Error: %s is a device; use --allow-devices to read it", filename);
+        return -1;
+    }
+
+    FILE *file = fromStdin ? stdin : fopen(filename, "rb");
+    if (!file) {
+        set_error(analysis, ERROR_IO, "Error opening file: %s: %s", filename, strerror(errno));
+        return -1;
+    }
+
+    // A decoded stream has no file descriptor, so it is never treated as a
+    // regular file: no mmap, no progress bar and no up-front range check.
+    struct decoder *decoder = NULL;
+    if (options->decompress) {
+        char reason[128];
+        FILE *decoded = open_decompressed(file, &decoder, reason, sizeof(reason));
+        if (!decoded) {
+            set_error(analysis, ERROR_DECOMPRESS, "Error decompressing file: %s: %s", analysis->filename, reason);
+            close_input(file);
+            return -1;
+        }
+        file = decoded;
+    }
+
+    int isRegular = fstat(fileno(file), &info) == 0 && S_ISREG(info.st_mode);
+    uint64_t available = isRegular ? (uint64_t)info.st_size : UINT64_MAX;
+    int ranged = options->offset > 0 || options->length != UINT64_MAX;
+    analysis->offset = options->offset;
+    analysis->ranged = ranged;
+
+    if (isRegular && (options->offset > available || (options->length != UINT64_MAX && options->length > available - options->offset))) {
+        set_error(analysis, ERROR_RANGE, "Error: %s: bytes %" PRIu64 "+%" PRIu64 " are beyond the end of the file (%" PRIu64 " bytes)",
+            analysis->filename, options->offset, options->length == UINT64_MAX ? 0 : options->length, available);
+        close_input(file);
+        return -1;
+    }
+    if (options->offset > 0 && skip_input(file, options->offset) != 0) {
+        set_error(analysis, ERROR_IO, "Error reading file: %s: %s", analysis->filename, strerror(errno));
+        close_input(file);
+        return -1;
+    }
+
+    // percent stays -1 while no bar has been drawn; a NULL bar is never drawn.
+    struct progress bar = {analysis->filename, 0, -1};
+    struct progress *progress = NULL;
+    uint64_t rangeLength = isRegular ? available - options->offset : UINT64_MAX;
+    if (options->length < rangeLength) {
+        rangeLength = options->length;
+    }
+    if (options->show_progress && isRegular && rangeLength >= PROGRESS_MIN_BYTES) {
+        bar.total = rangeLength;
+        progress = &bar;
+    }
+
+    if (options->sample_blocks && isRegular && available > (uint64_t)options->sample_blocks * SAMPLE_BLOCK_SIZE) {
+        int result = sample_file(file, available, options, analysis);
+        close_input(file);
+        return result;
+    }
+
+    if (!fromStdin && !ranged && analyze_file_parallel(file, options->keep_data, options->threads, progress, analysis) == 0) {
+        finish_progress(&bar);
+        close_input(file);
+        analysis->symbols = analysis->size;
+        return 0;
+    }
+
+    uint8_t *buffer = malloc(options->buffer_size);
+    if (!buffer) {
+        set_error(analysis, ERROR_MEMORY, "Out of memory reading file: %s", filename);
+        close_input(file);
+        return -1;
+    }
+    size_t bytesRead;
+    size_t dataCapacity = 0;
+    uint64_t remaining = options->length;
+
+    while (remaining > 0 && (bytesRead = fread(buffer, 1, remaining < options->buffer_size ? remaining : options->buffer_size, file)) > 0) {
+        remaining -= bytesRead;
+        for (size_t i = 0; i < bytesRead; i++) {
+            analysis->counts[buffer[i]]++;
+        }
+        if (analysis->size == 0) {
+            analysis->magic_length = bytesRead < MAGIC_LENGTH ? bytesRead : MAGIC_LENGTH;
+            memcpy(analysis->magic, buffer, analysis->magic_length);
+        }
+        analysis->size += bytesRead;
+        if (progress) {
+            update_progress(progress, analysis->size);
+        }
+
+        if (options->keep_data) {
+            if (analysis->data_length + bytesRead > dataCapacity) {
+                size_t capacity = dataCapacity ? dataCapacity : DEFAULT_BUFFER_SIZE;
+                while (capacity < analysis->data_length + bytesRead) {
+                    capacity *= 2;
+                }
+                uint8_t *grown = realloc(analysis->data, capacity);
+                if (!grown) {
+                    set_error(analysis, ERROR_MEMORY, "Out of memory reading file: %s", filename);
+                    free(buffer);
+                    finish_progress(&bar);
+                    free_analysis(analysis);
+                    close_input(file);
+                    return -1;
+                }
+                analysis->data = grown;
+                dataCapacity = capacity;
+            }
+            memcpy(analysis->data + analysis->data_length, buffer, bytesRead);
+            analysis->data_length += bytesRead;
+        }
+    }
+
+    free(buffer);
+    finish_progress(&bar);
+    if (ferror(file)) {
+        if (decoder && decoder->kind != COMPRESSION_NONE) {
+            set_error(analysis, ERROR_DECOMPRESS, "Error decompressing file: %s: %s", analysis->filename, decoder->error);
+        } else {
+            set_error(analysis, ERROR_IO, "Error reading file: %s: %s", analysis->filename,
+                decoder ? decoder->error : strerror(errno));
+        }
+        free_analysis(analysis);
+        close_input(file);
+        return -1;
+    }
+    close_input(file);
+
+    if (options->length != UINT64_MAX && remaining > 0) {
+        set_error(analysis, ERROR_RANGE, "Error: %s: bytes %" PRIu64 "+%" PRIu64 " are beyond the end of the input (%" PRIu64 " bytes)",
+            analysis->filename, options->offset, options->length, options->offset + analysis->size);
+        free_analysis(analysis);
+        return -1;
+    }
+    if (analysis->size == 0) {
+        set_error(analysis, ERROR_EMPTY, "Error: %s is empty, its entropy is undefined", analysis->filename);
+        return -1;
+    }
+    analysis->symbols = analysis->size;
+    return 0;
+}
+
+void free_analysis(struct file_analysis *analysis) {
+    if (analysis->data_mapped) {
+        munmap(analysis->data, analysis->data_length);
+    } else {
+        free(analysis->data);
+    }
+    analysis->data_mapped = 0;
+    analysis->data = NULL;
+    analysis->data_length = 0;
+}
+
+// The headline entropy of a file: bit-level when --bit is given, byte-level otherwise.
+static double primary_entropy(const struct file_analysis *analysis, const struct arguments *arguments) {
+    if (arguments->bit_level) {
+        return calculate_bit_entropy(analysis->counts, analysis->symbols);
+    }
+    return calculate_byte_entropy(analysis->counts, analysis->symbols);
+}
+
+// The metrics derived from the entropy per byte, shared by every output format.
+// A constant file has an unbounded coding ratio: INFINITY, which text and
+// markdown show as inf and ndjson as null.
+static double entropy_of_file(const struct file_analysis *analysis) {
+    return analysis->byte_entropy * analysis->symbols;
+}
+
+static double delta_bytes(const struct file_analysis *analysis) {
+    return analysis->size - entropy_of_file(analysis) / 8;
+}
+
+static double coding_ratio(const struct file_analysis *analysis) {
+    return analysis->byte_entropy > 0 ? 8 / analysis->byte_entropy : INFINITY;
+}
+
+// With --alphabet the bytes outside it are dropped before the transform, so
+// the result compares with the entropy of the same symbols.
+static double transformed_entropy(const struct file_analysis *analysis, const struct arguments *arguments) {
+    uint8_t *transformed = malloc(analysis->data_length);
+    uint8_t *kept = arguments->alphabet_file ? calloc(analysis->data_length, 1) : NULL;
+    if (!transformed || (arguments->alphabet_file && !kept)) {
+        free(transformed);
+        free(kept);
+        return NAN;
+    }
+
+    const uint8_t *source = analysis->data;
+    size_t length = analysis->data_length;
+    if (kept) {
+        length = 0;
+        for (size_t i = 0; i < analysis->data_length; i++) {
+            if (arguments->alphabet[analysis->data[i]]) {
+                kept[length++] = analysis->data[i];
+            }
+        }
+        source = kept;
+    }
+    apply_transform(&arguments->transform, source, transformed, length);
+    free(kept);
+
+    uint64_t counts[256] = {0};
+    for (size_t i = 0; i < length; i++) {
+        counts[transformed[i]]++;
+    }
+    free(transformed);
+
+    if (arguments->bit_level) {
+        return calculate_bit_entropy(counts, length);
+    }
+    return calculate_byte_entropy(counts, length);
+}
+
+// Drops the counts of bytes outside the --alphabet so entropy only covers valid symbols.
+static void apply_alphabet(struct file_analysis *analysis, const uint8_t alphabet[256]) {
+    for (int i = 0; i < 256; i++) {
+        if (!alphabet[i]) {
+            analysis->invalid_bytes += analysis->counts[i];
+            analysis->counts[i] = 0;
+        }
+    }
+    analysis->symbols = analysis->size - analysis->invalid_bytes;
+}
+
+// Returns -1 and sets analysis->error when the metrics are undefined.
+int compute_metrics(struct file_analysis *analysis, const struct arguments *arguments, double reference_entropy) {
+    if (arguments->alphabet_file) {
+        apply_alphabet(analysis, arguments->alphabet);
+        if (analysis->symbols == 0) {
+            set_error(analysis, ERROR_ALPHABET, "Error: %s has no bytes from the alphabet in %s", analysis->filename, arguments->alphabet_file);
+            return -1;
+        }
+    }
+
+    analysis->codepoint_entropy = NAN;
+    if (arguments->codepoint) {
+        analysis->codepoint_entropy = calculate_codepoint_entropy(analysis->data, analysis->data_length,
+            &analysis->characters, &analysis->distinct_characters, &analysis->invalid_sequences);
+        if (arguments->codepoint_strict && analysis->invalid_sequences > 0) {
+            set_error(analysis, ERROR_ENCODING, "Error: %s is not valid UTF-8 (%" PRIu64 " invalid sequences)", analysis->filename, analysis->invalid_sequences);
+            return -1;
+        }
+    }
+
+    analysis->byte_entropy = calculate_byte_entropy(analysis->counts, analysis->symbols);
+    analysis->bit_entropy = calculate_bit_entropy(analysis->counts, analysis->symbols);
+    analysis->normalized_entropy = arguments->bit_level ? analysis->bit_entropy / MAX_BIT_ENTROPY : analysis->byte_entropy / MAX_BYTE_ENTROPY;
+    analysis->distinct_bytes = 0;
+    for (int i = 0; i < 256; i++) {
+        analysis->distinct_bytes += analysis->counts[i] > 0;
+    }
+    analysis->hartley_entropy = log2(analysis->distinct_bytes);
+    analysis->index_of_coincidence = calculate_index_of_coincidence(analysis->counts, analysis->symbols);
+    analysis->collision_entropy = calculate_collision_entropy(analysis->counts, analysis->symbols);
+    analysis->chi_square = calculate_chi_square(analysis->counts, analysis->symbols);
+    analysis->classification = arguments->classify ? classify_file(analysis) : CLASS_UNKNOWN;
+    analysis->detected_type = detect_file_type(analysis);
+
+    for (int i = 0; i < arguments->ngram_count; i++) {
+        size_t n = arguments->ngram_sizes[i];
+        analysis->ngram_entropy[i] = analysis->data_length < n ? NAN : calculate_ngram_entropy(analysis->data, analysis->data_length, n);
+    }
+
+    analysis->conditional_entropy = NAN;
+    if (arguments->conditional && analysis->data_length >= 2) {
+        analysis->conditional_entropy = calculate_conditional_entropy(analysis->data, analysis->data_length);
+    }
+
+    analysis->delta_entropy = NAN;
+    if (arguments->delta_entropy && analysis->data_length >= 2) {
+        analysis->delta_entropy = calculate_delta_entropy(analysis->data, analysis->data_length);
+    }
+
+    analysis->pi_estimate = NAN;
+    analysis->pi_error_pct = NAN;
+    if (arguments->monte_carlo) {
+        analysis->pi_estimate = calculate_monte_carlo_pi(analysis->data, analysis->data_length);
+        analysis->pi_error_pct = 100 * fabs(analysis->pi_estimate - M_PI) / M_PI;
+    }
+
+    analysis->bit_stream_entropy = NAN;
+    if (arguments->bit_stream) {
+        analysis->bit_stream_entropy = calculate_bit_stream_entropy(analysis->counts, analysis->symbols, analysis->bit_position_entropy);
+    }
+
+    analysis->digram_cells = NULL;
+    if (arguments->digram_heatmap && analysis->data_length >= 2) {
+        analysis->digram_cells = calculate_digram_cells(analysis->data, analysis->data_length);
+    }
+
+    analysis->lz_phrases = 0;
+    analysis->lz_complexity = NAN;
+    if (arguments->lz && analysis->data_length >= 2) {
+        size_t length = analysis->data_length < LZ_MAX_LENGTH ? analysis->data_length : LZ_MAX_LENGTH;
+        int symbols = analysis->distinct_bytes > 2 ? analysis->distinct_bytes : 2;
+        analysis->lz_phrases = calculate_lz_complexity(analysis->data, length);
+        if (analysis->lz_phrases > 0) {
+            analysis->lz_complexity = analysis->lz_phrases * (log(length) / log(symbols)) / length;
+        }
+    }
+
+    analysis->pct_of_reference = NAN;
+    if (arguments->relative_to && reference_entropy > 0) {
+        analysis->pct_of_reference = 100 * primary_entropy(analysis, arguments) / reference_entropy;
+    }
+
+    analysis->entropy_rate = arguments->rate ? analysis->byte_entropy * arguments->rate : NAN;
+
+    analysis->kl_divergence = NAN;
+    analysis->total_variation = NAN;
+    if (arguments->reference) {
+        analysis->kl_divergence = calculate_kl_divergence(analysis->counts, analysis->symbols, arguments->reference_distribution);
+        analysis->total_variation = calculate_total_variation(analysis->counts, analysis->symbols, arguments->reference_distribution);
+    }
+
+    analysis->transformed_entropy = NAN;
+    if (arguments->transform.kind != TRANSFORM_NONE) {
+        analysis->transformed_entropy = transformed_entropy(analysis, arguments);
+    }
+    return 0;
+}
+
+// ANSI color for a normalized entropy, or "" when color is off.
+static const char *color_start(double normalized, int color) {
+    if (!color) {
+        return "";
+    }
+    return normalized < COLOR_YELLOW_FROM ? COLOR_GREEN : normalized < COLOR_RED_FROM ? COLOR_YELLOW : COLOR_RED;
+}
+
+static const char *color_end(int color) {
+    return color ? COLOR_RESET : "";
+}
+
+static void print_text(const struct file_analysis *analysis, const struct arguments *arguments) {
+    uint64_t size = analysis->size;
+    struct footnotes notes = {arguments->explain, 0, {0}};
+
+    if (analysis->ranged) {
+        printf("\n--- File: %s (bytes %" PRIu64 "-%" PRIu64 ") ---\n", analysis->filename, analysis->offset, analysis->offset + analysis->size - 1);
+    } else if (analysis->is_estimate) {
+        printf("\n--- File: %s (estimate from %" PRIu64 " of %" PRIu64 " bytes, seed %" PRIu64 ") ---\n",
+            analysis->filename, analysis->size, analysis->sampled_from, analysis->seed);
+    } else {
+        printf("\n--- File: %s ---\n", analysis->filename);
+    }
+    printf("---------------------------------------\n");
+
+    if (!arguments->bit_level || arguments->all_metrics) {
+        double entropy = analysis->byte_entropy;
+        double entropyPerByte = entropy / 8;
+        double entropyOfFile = entropy_of_file(analysis);
+
+        printf("Entropy per byte              : %s%.*f%s bits (%.*f bytes)%s\n", color_start(entropy / MAX_BYTE_ENTROPY, arguments->color), precision, entropy, color_end(arguments->color), precision, entropyPerByte, footnote(&notes, METRIC_ENTROPY_PER_BYTE));
+        printf("Entropy of file               : %.*f bits (%.*f bytes)%s\n", precision, entropyOfFile, precision, entropyOfFile / 8, footnote(&notes, METRIC_ENTROPY_OF_FILE));
+        printf("Size of file                  : %" PRIu64 " bytes\n", size);
+        printf("Delta                         : %.*f bytes (compressible theoretically)%s\n", precision, delta_bytes(analysis), footnote(&notes, METRIC_DELTA));
+        printf("Best Theoretical Coding ratio : %.*f%s\n", precision, coding_ratio(analysis), footnote(&notes, METRIC_CODING_RATIO));
+    }
+    if (arguments->bit_level || arguments->all_metrics) {
+        printf("Bit-level informational entropy: %s%.*f%s bits%s\n", color_start(analysis->bit_entropy / MAX_BIT_ENTROPY, arguments->color), precision, analysis->bit_entropy, color_end(arguments->color), footnote(&notes, METRIC_BIT_ENTROPY));
+    }
+    printf("Normalized entropy            : %.2f%% of maximum%s\n", 100 * analysis->normalized_entropy, footnote(&notes, METRIC_NORMALIZED_ENTROPY));
+    printf("Distinct bytes                : %d (Hartley entropy %.*f bits)%s\n", analysis->distinct_bytes, precision, analysis->hartley_entropy, footnote(&notes, METRIC_HARTLEY_ENTROPY));
+
+    if (isnan(analysis->index_of_coincidence)) {
+        printf("Index of coincidence          : n/a (needs at least 2 bytes)%s\n", footnote(&notes, METRIC_INDEX_OF_COINCIDENCE));
+    } else {
+        printf("Index of coincidence          : %.*f (collision entropy %.*f bits)%s\n", precision, analysis->index_of_coincidence, precision, analysis->collision_entropy, footnote(&notes, METRIC_INDEX_OF_COINCIDENCE));
+    }
+
+    if (analysis->detected_type) {
+        printf("Detected type                 : %s (from magic bytes)\n", analysis->detected_type);
+    }
+
+    if (arguments->classify) {
+        printf("Classification                : %s (chi-square %.2f)%s\n", file_class_names[analysis->classification], analysis->chi_square, footnote(&notes, METRIC_CLASSIFICATION));
+    }
+
+    if (arguments->alphabet_file) {
+        printf("Out-of-alphabet bytes         : %" PRIu64 " (%.2f%%)\n", analysis->invalid_bytes, 100.0 * analysis->invalid_bytes / size);
+    }
+
+    for (int i = 0; i < arguments->ngram_count; i++) {
+        size_t n = arguments->ngram_sizes[i];
+        char label[32];
+        snprintf(label, sizeof(label), "N-gram entropy (n=%zu)", n);
+        if (isnan(analysis->ngram_entropy[i])) {
+            printf("%-30s: n/a (file is shorter than %zu bytes)%s\n", label, n, footnote(&notes, METRIC_NGRAM_ENTROPY));
+        } else {
+            printf("%-30s: %.*f bits per byte%s\n", label, precision, analysis->ngram_entropy[i], footnote(&notes, METRIC_NGRAM_ENTROPY));
+        }
+    }
+
+    if (arguments->conditional) {
+        if (isnan(analysis->conditional_entropy)) {
+            printf("Conditional entropy           : n/a (needs at least 2 bytes)%s\n", footnote(&notes, METRIC_CONDITIONAL_ENTROPY));
+        } else {
+            printf("Conditional entropy           : %.*f bits per byte%s\n", precision, analysis->conditional_entropy, footnote(&notes, METRIC_CONDITIONAL_ENTROPY));
+        }
+    }
+
+    if (arguments->delta_entropy) {
+        if (isnan(analysis->delta_entropy)) {
+            printf("Delta entropy                 : n/a (needs at least 2 bytes)%s\n", footnote(&notes, METRIC_DELTA_ENTROPY));
+        } else {
+            printf("Delta entropy                 : %.*f bits per byte (%+.*f vs raw)%s\n", precision, analysis->delta_entropy, precision, analysis->delta_entropy - analysis->byte_entropy, footnote(&notes, METRIC_DELTA_ENTROPY));
+        }
+    }
+
+    if (arguments->monte_carlo) {
+        if (isnan(analysis->pi_estimate)) {
+            printf("Monte Carlo value for Pi      : n/a (needs at least 6 bytes)%s\n", footnote(&notes, METRIC_MONTE_CARLO_PI));
+        } else {
+            printf("Monte Carlo value for Pi      : %.9f (error %.2f%%)%s\n", analysis->pi_estimate, analysis->pi_error_pct, footnote(&notes, METRIC_MONTE_CARLO_PI));
+        }
+    }
+
+    if (arguments->codepoint) {
+        printf("Codepoint entropy             : %.*f bits per character (%" PRIu64 " characters, %" PRIu64 " distinct)%s\n",
+            precision, analysis->codepoint_entropy, analysis->characters, analysis->distinct_characters, footnote(&notes, METRIC_CODEPOINT_ENTROPY));
+        if (analysis->invalid_sequences > 0) {
+            printf("Invalid UTF-8 sequences       : %" PRIu64 " (counted as U+FFFD)\n", analysis->invalid_sequences);
+        }
+    }
+
+    if (arguments->bit_stream) {
+        printf("Bit-stream entropy            : %.*f bits per bit%s\n", precision, analysis->bit_stream_entropy, footnote(&notes, METRIC_BIT_STREAM_ENTROPY));
+        printf("Bit-position entropy (0 to 7) :");
+        for (int bit = 0; bit < 8; bit++) {
+            printf(" %.4f", analysis->bit_position_entropy[bit]);
+        }
+        printf("\n");
+    }
+
+    if (arguments->lz) {
+        if (isnan(analysis->lz_complexity)) {
+            printf("Lempel-Ziv complexity         : n/a%s\n", footnote(&notes, METRIC_LZ_COMPLEXITY));
+        } else {
+            printf("Lempel-Ziv complexity         : %.*f (%zu phrases)%s\n", precision, analysis->lz_complexity, analysis->lz_phrases, footnote(&notes, METRIC_LZ_COMPLEXITY));
+        }
+    }
+
+    if (arguments->relative_to) {
+        if (isnan(analysis->pct_of_reference)) {
+            printf("Percent of reference          : n/a (reference entropy is 0)%s\n", footnote(&notes, METRIC_PCT_OF_REFERENCE));
+        } else {
+            printf("Percent of reference          : %.2f%% of %s%s\n", analysis->pct_of_reference, arguments->relative_to, footnote(&notes, METRIC_PCT_OF_REFERENCE));
+        }
+    }
+
+    if (arguments->rate) {
+        printf("Entropy rate                  : %.3f bits/s at %g bytes/s%s\n", analysis->entropy_rate, arguments->rate, footnote(&notes, METRIC_ENTROPY_RATE));
+    }
+
+    if (arguments->reference) {
+        printf("KL divergence from reference  : %.*f bits per byte%s\n", precision, analysis->kl_divergence, footnote(&notes, METRIC_KL_DIVERGENCE));
+        printf("Total variation distance      : %.*f\n", precision, analysis->total_variation);
+    }
+
+    if (arguments->transform.kind != TRANSFORM_NONE) {
+        char label[64];
+        snprintf(label, sizeof(label), "Entropy after %s", arguments->transform.spec);
+        printf("%-30s: %.*f bits (%+.*f)%s\n", label, precision, analysis->transformed_entropy, precision, analysis->transformed_entropy - primary_entropy(analysis, arguments), footnote(&notes, METRIC_TRANSFORMED_ENTROPY));
+    }
+
+    if (arguments->timings) {
+        printf("Analysis time                 : %.3f ms\n", analysis->analysis_ms);
+    }
+
+    if (arguments->digram_heatmap) {
+        if (analysis->digram_cells) {
+            print_digram_heatmap(stdout, analysis->digram_cells, arguments->color);
+        } else {
+            printf("Digram heatmap                : n/a (needs at least 2 bytes)\n");
+        }
+    }
+
+    printf("---------------------------------------\n");
+    print_footnotes(&notes);
+    printf("\n");
+}
+
+static void print_markdown_cell(double value, const char *suffix) {
+    if (isnan(value)) {
+        printf(" n/a |");
+    } else if (isinf(value)) {
+        printf(" inf |");
+    } else {
+        printf(" %.*f%s |", precision, value, suffix);
+    }
+}
+
+static void print_markdown_header(const struct arguments *arguments) {
+    int columns = 0;
+
+    printf("| File | Size (bytes) |");
+    columns = 2;
+    if (arguments->sample_blocks) {
+        printf(" Estimate |");
+        columns++;
+    }
+    if (!arguments->bit_level || arguments->all_metrics) {
+        printf(" Entropy per byte (bits) | Entropy of file (bits) | Delta (bytes) | Coding ratio |");
+        columns += 4;
+    }
+    if (arguments->bit_level || arguments->all_metrics) {
+        printf(" Bit-level entropy (bits) |");
+        columns++;
+    }
+    printf(" Normalized entropy | Distinct bytes | Hartley entropy (bits) |");
+    columns += 3;
+    for (int i = 0; i < arguments->ngram_count; i++) {
+        printf(" N-gram entropy (n=%zu) |", arguments->ngram_sizes[i]);
+        columns++;
+    }
+    if (arguments->conditional) {
+        printf(" Conditional entropy |");
+        columns++;
+    }
+    if (arguments->delta_entropy) {
+        printf(" Delta entropy |");
+        columns++;
+    }
+    if (arguments->monte_carlo) {
+        printf(" Monte Carlo Pi | Pi error |");
+        columns += 2;
+    }
+    if (arguments->codepoint) {
+        printf(" Codepoint entropy (bits) |");
+        columns++;
+    }
+    if (arguments->bit_stream) {
+        printf(" Bit-stream entropy (bits) |");
+        columns++;
+    }
+    if (arguments->lz) {
+        printf(" LZ complexity |");
+        columns++;
+    }
+    if (arguments->relative_to) {
+        printf(" Percent of reference |");
+        columns++;
+    }
+    if (arguments->rate) {
+        printf(" Entropy rate (bit/s) |");
+        columns++;
+    }
+    if (arguments->reference) {
+        printf(" KL divergence (bits) | Total variation |");
+        columns += 2;
+    }
+    if (arguments->transform.kind != TRANSFORM_NONE) {
+        printf(" Entropy after %s |", arguments->transform.spec);
+        columns++;
+    }
+    if (arguments->classify) {
+        printf(" Classification |");
+        columns++;
+    }
+    if (arguments->alphabet_file) {
+        printf(" Out-of-alphabet bytes |");
+        columns++;
+    }
+    if (arguments->timings) {
+        printf(" Analysis time (ms) |");
+        columns++;
+    }
+    printf("\n|");
+    for (int i = 0; i < columns; i++) {
+        printf("---|");
+    }
+    printf("\n");
+}
+
+// Pipes would end the table cell early, so they are escaped.
+static void print_markdown_text(const char *text) {
+    for (; *text; text++) {
+        if (*text == '|') {
+            putchar('\\');
+        }
+        putchar(*text);
+    }
+}
+
+static void print_markdown_row(const struct file_analysis *analysis, const struct arguments *arguments) {
+    printf("| ");
+    print_markdown_text(analysis->filename);
+    printf(" |");
+
+    if (analysis->error[0]) {
+        printf(" | ");
+        print_markdown_text(analysis->error);
+        printf(" |\n");
+        return;
+    }
+
+    printf(" %" PRIu64 " |", analysis->size);
+    if (arguments->sample_blocks) {
+        printf(" %s |", analysis->is_estimate ? "yes" : "no");
+    }
+    if (!arguments->bit_level || arguments->all_metrics) {
+        print_markdown_cell(analysis->byte_entropy, "");
+        print_markdown_cell(entropy_of_file(analysis), "");
+        print_markdown_cell(delta_bytes(analysis), "");
+        print_markdown_cell(coding_ratio(analysis), "");
+    }
+    if (arguments->bit_level || arguments->all_metrics) {
+        print_markdown_cell(analysis->bit_entropy, "");
+    }
+    print_markdown_cell(analysis->normalized_entropy, "");
+    printf(" %d |", analysis->distinct_bytes);
+    print_markdown_cell(analysis->hartley_entropy, "");
+    for (int i = 0; i < arguments->ngram_count; i++) {
+        print_markdown_cell(analysis->ngram_entropy[i], "");
+    }
+    if (arguments->conditional) {
+        print_markdown_cell(analysis->conditional_entropy, "");
+    }
+    if (arguments->delta_entropy) {
+        print_markdown_cell(analysis->delta_entropy, "");
+    }
+    if (arguments->monte_carlo) {
+        print_markdown_cell(analysis->pi_estimate, "");
+        print_markdown_cell(analysis->pi_error_pct, "%");
+    }
+    if (arguments->codepoint) {
+        print_markdown_cell(analysis->codepoint_entropy, "");
+    }
+    if (arguments->bit_stream) {
+        print_markdown_cell(analysis->bit_stream_entropy, "");
+    }
+    if (arguments->lz) {
+        print_markdown_cell(analysis->lz_complexity, "");
+    }
+    if (arguments->relative_to) {
+        print_markdown_cell(analysis->pct_of_reference, "%");
+    }
+    if (arguments->rate) {
+        print_markdown_cell(analysis->entropy_rate, "");
+    }
+    if (arguments->reference) {
+        print_markdown_cell(analysis->kl_divergence, "");
+        print_markdown_cell(analysis->total_variation, "");
+    }
+    if (arguments->transform.kind != TRANSFORM_NONE) {
+        print_markdown_cell(analysis->transformed_entropy, "");
+    }
+    if (arguments->classify) {
+        printf(" %s |", file_class_names[analysis->classification]);
+    }
+    if (arguments->alphabet_file) {
+        printf(" %" PRIu64 " |", analysis->invalid_bytes);
+    }
+    if (arguments->timings) {
+        print_markdown_cell(analysis->analysis_ms, "");
+    }
+    printf("\n");
+}
+
+static void write_json_string(FILE *out, const char *text) {
+    fputc('"', out);
+    for (; *text; text++) {
+        unsigned char c = *text;
+        if (c == '"' || c == '\\') {
+            fprintf(out, "\\%c", c);
+        } else if (c == '\n') {
+            fprintf(out, "\\n");
+        } else if (c == '\t') {
+            fprintf(out, "\\t");
+        } else if (c < 0x20) {
+            fprintf(out, "\\u%04x", c);
+        } else {
+            fputc(c, out);
+        }
+    }
+    fputc('"', out);
+}
+
+static void print_json_string(const char *text) {
+    write_json_string(stdout, text);
+}
+
+// JSON has no NaN or infinity, so unavailable and unbounded values become null.
+static void print_json_number(const char *key, double value) {
+    printf(",\"%s\":", key);
+    if (isfinite(value)) {
+        printf("%.17g", value);
+    } else {
+        printf("null");
+    }
+}
+
+// Entropy values in bits, optionally as integer millibits under a "_millibits"
+// key for consumers that cannot parse floating point.
+static void print_json_entropy(const char *key, double bits, int millibits) {
+    if (!millibits) {
+        print_json_number(key, bits);
+        return;
+    }
+
+    printf(",\"%s_millibits\":", key);
+    if (isfinite(bits)) {
+        printf("%lld", llround(bits * 1000));
+    } else {
+        printf("null");
+    }
+}
+
+// The current time in UTC as ISO 8601, such as 2024-05-01T12:00:00Z.
+static void format_timestamp(char *timestamp, size_t size) {
+    time_t now = time(NULL);
+    struct tm utc;
+    gmtime_r(&now, &utc);
+    strftime(timestamp, size, "%Y-%m-%dT%H:%M:%SZ", &utc);
+}
+
+// The --label keys, last on every line so they leave the other keys in place.
+static void print_json_label(const struct arguments *arguments) {
+    if (arguments->label) {
+        printf(",\"label\":");
+        print_json_string(arguments->label);
+        printf(",\"timestamp\":\"%s\"", arguments->timestamp);
+    }
+}
+
+// Writes one compact JSON object per line and flushes it, so long scans can be
+// followed with tail -f. Keys come in a fixed order that consumers diff on:
+// adding a field must not move the others, and result_schema lists it too.
+static void print_ndjson(const struct file_analysis *analysis, const struct arguments *arguments) {
+    printf("{\"filename\":");
+    print_json_string(analysis->filename);
+
+    if (analysis->error[0]) {
+        printf(",\"error\":");
+        print_json_string(analysis->error);
+        printf(",\"error_kind\":\"%s\"", error_kind_names[analysis->error_kind]);
+        print_json_label(arguments);
+        printf("}\n");
+        fflush(stdout);
+        return;
+    }
+
+    printf(",\"size_bytes\":%" PRIu64, analysis->size);
+    if (analysis->ranged) {
+        printf(",\"offset\":%" PRIu64, analysis->offset);
+    }
+    if (arguments->sample_blocks) {
+        printf(",\"is_estimate\":%s", analysis->is_estimate ? "true" : "false");
+    }
+    if (analysis->is_estimate) {
+        printf(",\"sampled_from_bytes\":%" PRIu64 ",\"sample_seed\":%" PRIu64, analysis->sampled_from, analysis->seed);
+    }
+    print_json_entropy("byte_entropy", analysis->byte_entropy, arguments->millibits);
+    print_json_entropy("bit_entropy", analysis->bit_entropy, arguments->millibits);
+    print_json_entropy("entropy_of_file", entropy_of_file(analysis), arguments->millibits);
+    print_json_number("delta_bytes", delta_bytes(analysis));
+    print_json_number("coding_ratio", coding_ratio(analysis));
+    print_json_number("normalized_entropy", analysis->normalized_entropy);
+    printf(",\"detected_type\":");
+    if (analysis->detected_type) {
+        print_json_string(analysis->detected_type);
+    } else {
+        printf("null");
+    }
+    printf(",\"distinct_bytes\":%d", analysis->distinct_bytes);
+    print_json_entropy("hartley_entropy", analysis->hartley_entropy, arguments->millibits);
+    print_json_number("index_of_coincidence", analysis->index_of_coincidence);
+    print_json_entropy("collision_entropy", analysis->collision_entropy, arguments->millibits);
+
+    if (arguments->ngram_count > 0) {
+        printf(",\"ngram_entropy\":[");
+        for (int i = 0; i < arguments->ngram_count; i++) {
+            printf("%s{\"n\":%zu", i ? "," : "", arguments->ngram_sizes[i]);
+            print_json_entropy("entropy", analysis->ngram_entropy[i], arguments->millibits);
+            printf("}");
+        }
+        printf("]");
+    }
+    if (arguments->conditional) {
+        print_json_entropy("conditional_entropy", analysis->conditional_entropy, arguments->millibits);
+    }
+    if (arguments->delta_entropy) {
+        print_json_entropy("delta_entropy", analysis->delta_entropy, arguments->millibits);
+    }
+    if (arguments->monte_carlo) {
+        print_json_number("pi_estimate", analysis->pi_estimate);
+        print_json_number("pi_error_pct", analysis->pi_error_pct);
+    }
+    if (arguments->codepoint) {
+        print_json_entropy("codepoint_entropy", analysis->codepoint_entropy, arguments->millibits);
+        printf(",\"invalid_utf8_sequences\":%" PRIu64, analysis->invalid_sequences);
+    }
+    if (arguments->bit_stream) {
+        print_json_entropy("bit_stream_entropy", analysis->bit_stream_entropy, arguments->millibits);
+        printf(",\"bit_position_entropy\":[");
+        for (int bit = 0; bit < 8; bit++) {
+            printf("%s%.17g", bit ? "," : "", analysis->bit_position_entropy[bit]);
+        }
+        printf("]");
+    }
+    if (arguments->lz) {
+        print_json_number("lz_complexity", analysis->lz_complexity);
+    }
+    if (arguments->relative_to) {
+        print_json_number("pct_of_reference", analysis->pct_of_reference);
+    }
+    if (arguments->rate) {
+        print_json_number("entropy_rate_bits_per_sec", analysis->entropy_rate);
+    }
+    if (arguments->reference) {
+        print_json_number("kl_divergence", analysis->kl_divergence);
+        print_json_number("total_variation", analysis->total_variation);
+    }
+    if (arguments->transform.kind != TRANSFORM_NONE) {
+        printf(",\"transform\":");
+        print_json_string(arguments->transform.spec);
+        print_json_entropy("transformed_entropy", analysis->transformed_entropy, arguments->millibits);
+    }
+    if (arguments->classify) {
+        printf(",\"classification\":\"%s\"", file_class_names[analysis->classification]);
+        print_json_number("chi_square", analysis->chi_square);
+    }
+    if (arguments->alphabet_file) {
+        printf(",\"invalid_bytes\":%" PRIu64, analysis->invalid_bytes);
+        print_json_number("invalid_fraction", (double)analysis->invalid_bytes / analysis->size);
+    }
+    if (arguments->timings) {
+        print_json_number("analysis_ms", analysis->analysis_ms);
+    }
+    if (arguments->include_histogram) {
+        // [byte, count] pairs in byte order; zero counts are left out to keep lines short.
+        const char *separator = "";
+        printf(",\"byte_counts\":[");
+        for (int i = 0; i < 256; i++) {
+            if (analysis->counts[i] > 0) {
+                printf("%s[%d,%" PRIu64 "]", separator, i, analysis->counts[i]);
+                separator = ",";
+            }
+        }
+        printf("]");
+    }
+
+    print_json_label(arguments);
+    printf("}\n");
+    fflush(stdout);
+}
+
+// Escapes text for HTML and SVG, in element content and quoted attributes.
+static void write_xml_text(FILE *out, const char *text) {
+    for (; *text; text++) {
+        switch (*text) {
+            case '&': fputs("&amp;", out); break;
+            case '<': fputs("&lt;", out); break;
+            case '>': fputs("&gt;", out); break;
+            case '"': fputs("&quot;", out); break;
+            case '\'': fputs("&#39;", out); break;
+            default: fputc(*text, out);
+        }
+    }
+}
+
+// Draws the byte counts as an SVG bar chart of bins equal ranges of byte
+// values, with the largest bin count marked on the y axis. log_scale sizes bars
+// by log(count + 1): rare values stay visible, but bar heights no longer
+// compare proportionally. Tooltips always show the true counts.
+void render_histogram_svg(FILE *out, const struct file_analysis *analysis, int bins, int log_scale) {
+    const int left = 60, top = 40, plotWidth = 768, plotHeight = 220;
+    int binSize = 256 / bins;
+    uint64_t binCounts[256] = {0};
+    uint64_t maxCount = 0;
+    for (int i = 0; i < 256; i++) {
+        binCounts[i / binSize] += analysis->counts[i];
+    }
+    for (int i = 0; i < bins; i++) {
+        if (binCounts[i] > maxCount) {
+            maxCount = binCounts[i];
+        }
+    }
+
+    fprintf(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"%d\" height=\"%d\" viewBox=\"0 0 %d %d\" font-family=\"sans-serif\" font-size=\"12\">\n",
+        left + plotWidth + 20, top + plotHeight + 50, left + plotWidth + 20, top + plotHeight + 50);
+    fprintf(out, "<title>Byte distribution of ");
+    write_xml_text(out, analysis->filename);
+    fprintf(out, "</title>\n<text x=\"%d\" y=\"20\" font-size=\"14\">Byte distribution of ", left);
+    write_xml_text(out, analysis->filename);
+    fprintf(out, "</text>\n");
+
+    for (int i = 0; i < bins; i++) {
+        double height = 0;
+        if (maxCount && log_scale) {
+            height = plotHeight * log1p(binCounts[i]) / log1p(maxCount);
+        } else if (maxCount) {
+            height = (double)plotHeight * binCounts[i] / maxCount;
+        }
+        fprintf(out, "<rect x=\"%d\" y=\"%.2f\" width=\"%d\" height=\"%.2f\" fill=\"#4a7ebb\">",
+            left + i * plotWidth / bins, top + plotHeight - height, plotWidth / bins, height);
+        if (binSize == 1) {
+            fprintf(out, "<title>0x%02X: %" PRIu64 "</title></rect>\n", i, binCounts[i]);
+        } else {
+            fprintf(out, "<title>0x%02X-0x%02X: %" PRIu64 "</title></rect>\n", i * binSize, (i + 1) * binSize - 1, binCounts[i]);
+        }
+    }
+
+    fprintf(out, "<path d=\"M%d %d V%d H%d\" stroke=\"#222\" fill=\"none\"/>\n", left, top, top + plotHeight, left + plotWidth);
+    fprintf(out, "<text x=\"%d\" y=\"%d\" text-anchor=\"end\">%" PRIu64 "</text>\n", left - 5, top + 4, maxCount);
+    fprintf(out, "<text x=\"%d\" y=\"%d\" text-anchor=\"end\">0</text>\n", left - 5, top + plotHeight + 4);
+    for (int i = 0; i <= 256; i += 64) {
+        fprintf(out, "<text x=\"%d\" y=\"%d\" text-anchor=\"middle\">0x%02X</text>\n", left + i * plotWidth / 256, top + plotHeight + 16, i == 256 ? 0xFF : i);
+    }
+    fprintf(out, "<text x=\"%d\" y=\"%d\" text-anchor=\"middle\">Byte value</text>\n", left + plotWidth / 2, top + plotHeight + 36);
+    fprintf(out, "<text x=\"15\" y=\"%d\" text-anchor=\"middle\" transform=\"rotate(-90 15 %d)\">Count%s</text>\n", top + plotHeight / 2, top + plotHeight / 2, log_scale ? " (log scale)" : "");
+    fprintf(out, "</svg>\n");
+}
+
+// A standalone HTML5 page with the CSS inline, so the report can be mailed or
+// archived as a single file.
+static void print_html_header(const struct arguments *arguments) {
+    printf("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>Entropy report</title>\n");
+    printf("<style>\n"
+        "body { font-family: sans-serif; margin: 2em; color: #222; }\n"
+        "table { border-collapse: collapse; }\n"
+        "th, td { border: 1px solid #ccc; padding: 0.3em 0.8em; }\n"
+        "th { background: #f0f0f0; }\n"
+        "td.number { text-align: right; font-family: monospace; }\n"
+        "td.error { color: #b00; }\n"
+        "</style>\n</head>\n<body>\n<h1>Entropy report</h1>\n");
+    if (arguments->label) {
+        printf("<p>");
+        write_xml_text(stdout, arguments->label);
+        printf(", <time datetime=\"%s\">%s</time></p>\n", arguments->timestamp, arguments->timestamp);
+    }
+    printf("<table>\n<thead><tr><th>File</th><th>Size (bytes)</th><th>Entropy per byte (bits)</th>"
+        "<th>Bit-level entropy (bits)</th><th>Normalized entropy</th><th>Distinct bytes</th></tr></thead>\n<tbody>\n");
+}
+
+static void print_html_row(const struct file_analysis *analysis) {
+    printf("<tr><td>");
+    write_xml_text(stdout, analysis->filename);
+    printf("%s</td>", analysis->is_estimate ? " (estimate)" : "");
+
+    if (analysis->error[0]) {
+        printf("<td class=\"error\" colspan=\"5\">");
+        write_xml_text(stdout, analysis->error);
+        printf("</td></tr>\n");
+        return;
+    }
+
+    printf("<td class=\"number\">%" PRIu64 "</td>", analysis->size);
+    printf("<td class=\"number\">%.*f</td>", precision, analysis->byte_entropy);
+    printf("<td class=\"number\">%.*f</td>", precision, analysis->bit_entropy);
+    printf("<td class=\"number\">%.2f%%</td>", 100 * analysis->normalized_entropy);
+    printf("<td class=\"number\">%d</td></tr>\n", analysis->distinct_bytes);
+}
+
+// Closes the table; a report on a single file also gets an SVG chart of its
+// byte counts.
+static void print_html_footer(const struct file_analysis *results, size_t count, const struct arguments *arguments) {
+    printf("</tbody>\n</table>\n");
+
+    if (count == 1 && !results[0].error[0]) {
+        printf("<h2>Byte distribution</h2>\n");
+        render_histogram_svg(stdout, &results[0], arguments->histogram_bins, arguments->histogram_log);
+    }
+    printf("</body>\n</html>\n");
+}
+
+// Reports the file on stderr when its entropy per byte falls outside the
+// --threshold-min/--threshold-max range. Returns non-zero on a violation.
+static int check_thresholds(const struct file_analysis *analysis, const struct arguments *arguments) {
+    if (analysis->byte_entropy < arguments->threshold_min) {
+        log_warn("Threshold violation: %s: entropy %.*f is below minimum %.*f", analysis->filename, precision, analysis->byte_entropy, precision, arguments->threshold_min);
+        return 1;
+    }
+    if (analysis->byte_entropy > arguments->threshold_max) {
+        log_warn("Threshold violation: %s: entropy %.*f is above maximum %.*f", analysis->filename, precision, analysis->byte_entropy, precision, arguments->threshold_max);
+        return 1;
+    }
+    return 0;
+}
+
+static double elapsed_ms(const struct timespec *start) {
+    struct timespec now;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+    return (now.tv_sec - start->tv_sec) * 1e3 + (now.tv_nsec - start->tv_nsec) / 1e6;
+}
+
+static int needs_data(const struct arguments *arguments);
+static int load_cached_counts(struct analysis_cache *cache, const char *filename, const struct stat *info,
+    const struct arguments *arguments, struct file_analysis *analysis);
+static void store_cached_counts(struct analysis_cache *cache, const char *filename, const struct stat *info,
+    const struct arguments *arguments, const struct file_analysis *analysis);
+static int finish_entropy(struct file_analysis *analysis, const struct arguments *arguments, double reference_entropy, const struct timespec *start);
+
+// Analyzes one file into *analysis, keeping only the counts and metrics, and
+// returns its exit status. Errors are left in analysis->error for print_result.
+int calculate_entropy(const char *filename, const struct arguments *arguments, double reference_entropy, struct file_analysis *analysis) {
+    struct timespec start;
+    struct stat info;
+
+    clock_gettime(CLOCK_MONOTONIC, &start);
+    // The cache only holds byte counts, so metrics that need the bytes
+    // themselves always read the file. The file is stat'ed before reading, so
+    // a change during the read leaves a stale mtime that forces a recount.
+    int cacheable = arguments->cache && !needs_data(arguments) && !arguments->sample_blocks && strcmp(filename, "-") != 0
+        && stat(filename, &info) == 0 && S_ISREG(info.st_mode);
+    if (!cacheable || !load_cached_counts(arguments->cache, filename, &info, arguments, analysis)) {
+        struct analysis_options options = {
+            .keep_data = needs_data(arguments),
+            .threads = arguments->threads,
+            .buffer_size = arguments->buffer_size,
+            .show_progress = arguments->progress,
+            .decompress = arguments->decompress,
+            .offset = arguments->offset,
+            .length = arguments->length,
+            .allow_devices = arguments->allow_devices,
+            .sample_blocks = arguments->sample_blocks,
+            .seed = arguments->seed,
+        };
+        analyze_file(filename, &options, analysis);
+        if (cacheable && !analysis->error[0]) {
+            store_cached_counts(arguments->cache, filename, &info, arguments, analysis);
+        }
+    }
+    return finish_entropy(analysis, arguments, reference_entropy, &start);
+}
+
+// Metrics that depend on byte order need the raw bytes kept after counting.
+static int needs_data(const struct arguments *arguments) {
+    return arguments->ngram_count > 0 || arguments->conditional || arguments->delta_entropy
+        || arguments->monte_carlo || arguments->lz || arguments->codepoint || arguments->digram_heatmap || arguments->transform.kind != TRANSFORM_NONE;
+}
+
+// Computes the metrics of a counted file unless counting failed, drops the raw
+// bytes and returns the file's exit status.
+static int finish_entropy(struct file_analysis *analysis, const struct arguments *arguments, double reference_entropy, const struct timespec *start) {
+    if (!analysis->error[0]) {
+        compute_metrics(analysis, arguments, reference_entropy);
+    }
+    analysis->analysis_ms = elapsed_ms(start);
+    free_analysis(analysis);
+    log_info("%s: analyzed in %.3f ms", analysis->filename, analysis->analysis_ms);
+
+    if (analysis->error[0]) {
+        return EXIT_FILE_ERROR;
+    }
+    return check_thresholds(analysis, arguments) ? EXIT_THRESHOLD : EXIT_OK;
+}
+
+// One aligned line per file, like ls -l: entropy, size and name. Failed files
+// show ERR in the entropy column, followed by the error message.
+static void print_compact(const struct file_analysis *const *results, size_t count, const struct arguments *arguments) {
+    int sizeWidth = 1;
+    for (size_t i = 0; i < count; i++) {
+        if (!results[i]->error[0]) {
+            int width = snprintf(NULL, 0, "%" PRIu64, results[i]->size);
+            sizeWidth = width > sizeWidth ? width : sizeWidth;
+        }
+    }
+
+    int entropyWidth = precision + 2 > 3 ? precision + 2 : 3;
+    for (size_t i = 0; i < count; i++) {
+        const struct file_analysis *analysis = results[i];
+        if (analysis->error[0]) {
+            printf("%-*s %*s %s  %s\n", entropyWidth, "ERR", sizeWidth, "-", analysis->filename, analysis->error);
+        } else {
+            printf("%*.*f %*" PRIu64 " %s%s\n", entropyWidth, precision, primary_entropy(analysis, arguments), sizeWidth, analysis->size, analysis->filename,
+                analysis->is_estimate ? "  (estimate)" : "");
+        }
+    }
+}
+
+// Just the entropy, for scripts: THRESH=$(./main --entropy-only file). Failed
+// files print nothing on stdout.
+static void print_entropy_only(const struct file_analysis *analysis, const struct arguments *arguments) {
+    if (analysis->error[0]) {
+        fprintf(stderr, "%s\n", analysis->error);
+    } else if (arguments->entropy_only_names) {
+        printf("%.*f\t%s\n", precision, primary_entropy(analysis, arguments), analysis->filename);
+    } else {
+        printf("%.*f\n", precision, primary_entropy(analysis, arguments));
+    }
+}
+
+void print_result(const struct file_analysis *analysis, const struct arguments *arguments) {
+    if (arguments->entropy_only) {
+        print_entropy_only(analysis, arguments);
+    } else if (arguments->format == FORMAT_MARKDOWN) {
+        print_markdown_row(analysis, arguments);
+    } else if (arguments->format == FORMAT_NDJSON) {
+        print_ndjson(analysis, arguments);
+    } else if (arguments->format == FORMAT_HTML) {
+        print_html_row(analysis);
+    } else if (analysis->error[0]) {
+        fprintf(stderr, "%s\n", analysis->error);
+    } else {
+        print_text(analysis, arguments);
+    }
+}
+
+static int compare_results(const void *a, const void *b, void *context) {
+    const struct file_analysis *left = *(const struct file_analysis *const *)a;
+    const struct file_analysis *right = *(const struct file_analysis *const *)b;
+    const struct arguments *arguments = context;
+
+    // Failed files have no metrics, so they go last in input order.
+    if (!left->error[0] != !right->error[0]) {
+        return left->error[0] ? 1 : -1;
+    }
+
+    int order = 0;
+    if (!left->error[0]) {
+        if (arguments->sort == SORT_ENTROPY) {
+            double leftEntropy = arguments->bit_level ? left->bit_entropy : left->byte_entropy;
+            double rightEntropy = arguments->bit_level ? right->bit_entropy : right->byte_entropy;
+            order = (leftEntropy > rightEntropy) - (leftEntropy < rightEntropy);
+        } else if (arguments->sort == SORT_SIZE) {
+            order = (left->size > right->size) - (left->size < right->size);
+        } else if (arguments->sort == SORT_NAME) {
+            order = strcmp(left->filename, right->filename);
+        }
+        if (arguments->reverse) {
+            order = -order;
+        }
+    }
+    // Results live in one array in input order, so comparing addresses keeps ties stable.
+    return order ? order : (left > right) - (left < right);
+}
+
+void sort_results(const struct file_analysis **results, size_t count, const struct arguments *arguments) {
+    qsort_r(results, count, sizeof(*results), compare_results, (void *)arguments);
+}
+
+// Draws the digram matrix as a grid, one character per cell. The shade grows
+// with log(count + 1) so rare pairs stay visible next to common ones, and a
+// cell without a single pair is left blank: structured data shows mostly
+// empty space, random data a solid block.
+void print_digram_heatmap(FILE *out, const uint64_t *cells, int color) {
+    static const char ramp[] = ".:-=+*#%@";
+    const int levels = sizeof(ramp) - 1;
+    const int span = 256 / DIGRAM_CELLS;
+    uint64_t maxCount = 0;
+
+    for (int i = 0; i < DIGRAM_CELLS * DIGRAM_CELLS; i++) {
+        maxCount = cells[i] > maxCount ? cells[i] : maxCount;
+    }
+
+    fprintf(out, "Digram heatmap (row: byte, column: next byte, %dx%d values per cell):\n", span, span);
+    fprintf(out, "    ");
+    for (int column = 0; column < DIGRAM_CELLS; column += 16) {
+        fprintf(out, column + 16 < DIGRAM_CELLS ? "%-16.2x" : "%.2x", column * span);
+    }
+    fprintf(out, "\n");
+    for (int row = 0; row < DIGRAM_CELLS; row++) {
+        fprintf(out, " %02x ", row * span);
+        for (int column = 0; column < DIGRAM_CELLS; column++) {
+            uint64_t count = cells[row * DIGRAM_CELLS + column];
+            if (count == 0) {
+                fputc(' ', out);
+                continue;
+            }
+            double shade = maxCount > 1 ? log1p(count) / log1p(maxCount) : 1.0;
+            if (color) {
+                // The 24 grays of the 256-color palette, skipping the darkest few
+                fprintf(out, "\033[48;5;%dm \033[0m", 236 + (int)(shade * 19 + 0.5));
+            } else {
+                fputc(ramp[(int)(shade * (levels - 1) + 0.5)], out);
+            }
+        }
+        fprintf(out, "\n");
+    }
+}
+
+// Charts how the entropies per byte of all analyzed files spread over equal
+// bins from 0 to 8 bits. The last bin is closed, so exactly 8.0 lands in it.
+void print_entropy_histogram(const struct file_analysis *results, size_t count, int bins, int color) {
+    uint32_t binCounts[MAX_HISTOGRAM_BINS] = {0};
+    uint32_t maxCount = 0;
+    size_t analyzed = 0;
+
+    for (size_t i = 0; i < count; i++) {
+        if (results[i].error[0]) {
+            continue;
+        }
+        int bin = (int)(results[i].byte_entropy / 8 * bins);
+        bin = bin < 0 ? 0 : bin >= bins ? bins - 1 : bin;
+        binCounts[bin]++;
+        analyzed++;
+    }
+    for (int i = 0; i < bins; i++) {
+        if (binCounts[i] > maxCount) {
+            maxCount = binCounts[i];
+        }
+    }
+
+    printf("\n--- Entropy distribution (%zu files) ---\n", analyzed);
+    printf("---------------------------------------\n");
+    for (int i = 0; i < bins; i++) {
+        int width = maxCount ? (int)((uint64_t)binCounts[i] * HISTOGRAM_WIDTH / maxCount) : 0;
+        printf("%.2f - %.2f %c ", 8.0 * i / bins, 8.0 * (i + 1) / bins, i == bins - 1 ? ']' : ')');
+        printf("%s", color_start((i + 0.5) / bins, color));
+        for (int j = 0; j < width; j++) {
+            printf("\u2588");
+        }
+        printf("%s", color_end(color));
+        printf("%*s %" PRIu32 "\n", HISTOGRAM_WIDTH - width, "", binCounts[i]);
+    }
+    printf("---------------------------------------\n\n");
+}
+
+// Charts the byte distributions of two files back to back from a centre axis,
+// the first going left and the second right. Bars are frequencies on a common
+// scale, so files of very different sizes can still be compared.
+void print_distribution_diff(const struct file_analysis *left, const struct file_analysis *right, int bins) {
+    const int halfWidth = HISTOGRAM_WIDTH / 2;
+    int binWidth = 256 / bins;
+    double leftShares[256] = {0};
+    double rightShares[256] = {0};
+    double maxShare = 0.0;
+
+    for (int i = 0; i < bins; i++) {
+        for (int j = i * binWidth; j < (i + 1) * binWidth; j++) {
+            leftShares[i] += (double)left->counts[j] / left->symbols;
+            rightShares[i] += (double)right->counts[j] / right->symbols;
+        }
+        maxShare = fmax(maxShare, fmax(leftShares[i], rightShares[i]));
+    }
+
+    printf("\n--- Byte distribution: %s (left) vs %s (right) ---\n", left->filename, right->filename);
+    printf("---------------------------------------\n");
+    for (int i = 0; i < bins; i++) {
+        int leftWidth = maxShare > 0 ? (int)lround(leftShares[i] / maxShare * halfWidth) : 0;
+        int rightWidth = maxShare > 0 ? (int)lround(rightShares[i] / maxShare * halfWidth) : 0;
+        printf("%02x-%02x %6.2f%% %*s", i * binWidth, (i + 1) * binWidth - 1, leftShares[i] * 100, halfWidth - leftWidth, "");
+        for (int j = 0; j < leftWidth; j++) {
+            printf("\u2588");
+        }
+        printf("|");
+        for (int j = 0; j < rightWidth; j++) {
+            printf("\u2588");
+        }
+        printf("%*s %6.2f%%\n", halfWidth - rightWidth, "", rightShares[i] * 100);
+    }
+    printf("---------------------------------------\n\n");
+}
+
+static int compare_doubles(const void *a, const void *b) {
+    double left = *(const double *)a;
+    double right = *(const double *)b;
+    return (left > right) - (left < right);
+}
+
+// Groups the analyzed files by entropy with 1-D k-means and prints each
+// cluster's size, range and centroid. Starting centroids are spread over the
+// sorted entropies, so the result is the same on every run.
+void print_entropy_clusters(const struct file_analysis *results, size_t count, int clusters, int bit_level) {
+    double *values = malloc((count + 1) * sizeof(*values));
+    size_t analyzed = 0;
+    if (!values) {
+        fprintf(stderr, "Out of memory\n");
+        return;
+    }
+    for (size_t i = 0; i < count; i++) {
+        if (!results[i].error[0]) {
+            values[analyzed++] = bit_level ? results[i].bit_entropy : results[i].byte_entropy;
+        }
+    }
+    qsort(values, analyzed, sizeof(*values), compare_doubles);
+
+    size_t k = (size_t)clusters < analyzed ? (size_t)clusters : analyzed;
+    double centroids[MAX_CLUSTERS];
+    size_t members[MAX_CLUSTERS];
+    double low[MAX_CLUSTERS];
+    double high[MAX_CLUSTERS];
+    for (size_t c = 0; c < k; c++) {
+        centroids[c] = values[(2 * c + 1) * analyzed / (2 * k)];
+    }
+
+    // Centroids stay sorted, so each value belongs to the last centroid it is
+    // at least as close to as the one before it.
+    for (int iteration = 0; iteration < 100; iteration++) {
+        double sums[MAX_CLUSTERS] = {0};
+        size_t c = 0;
+        memset(members, 0, sizeof(members));
+        for (size_t i = 0; i < analyzed; i++) {
+            while (c + 1 < k && fabs(values[i] - centroids[c + 1]) <= fabs(values[i] - centroids[c])) {
+                c++;
+            }
+            if (members[c]++ == 0) {
+                low[c] = values[i];
+            }
+            high[c] = values[i];
+            sums[c] += values[i];
+        }
+
+        int moved = 0;
+        for (c = 0; c < k; c++) {
+            double centroid = members[c] ? sums[c] / members[c] : centroids[c];
+            moved |= centroid != centroids[c];
+            centroids[c] = centroid;
+        }
+        if (!moved) {
+            break;
+        }
+    }
+
+    printf("\n--- Entropy clusters (%zu files) ---\n", analyzed);
+    printf("---------------------------------------\n");
+    int number = 0;
+    for (size_t c = 0; c < k; c++) {
+        if (members[c]) {
+            printf("Cluster %d: %zu file%s, %.4f - %.4f bits, centroid %.4f\n",
+                ++number, members[c], members[c] == 1 ? "" : "s", low[c], high[c], centroids[c]);
+        }
+    }
+    printf("---------------------------------------\n\n");
+    free(values);
+}
+
+// Reports on stderr what a run over several files did: how many files were
+// analyzed, failed and skipped, how many bytes were counted and how long it
+// took. It shows at the default log level, like warnings, and --quiet hides it.
+static void print_run_summary(const struct file_analysis *results, size_t count, size_t skipped, double ms) {
+    size_t failed = 0;
+    uint64_t totalBytes = 0;
+
+    if (log_level < LOG_WARN) {
+        return;
+    }
+    for (size_t i = 0; i < count; i++) {
+        if (results[i].error[0]) {
+            failed++;
+        } else {
+            totalBytes += results[i].size;
+        }
+    }
+    fflush(stdout);
+    fprintf(stderr, "Summary: %zu analyzed, %zu failed, %zu skipped by size limits, %" PRIu64 " bytes in %.3f s\n",
+        count - failed, failed, skipped, totalBytes, ms / 1000);
+}
+
+// Analyzes and prints every file in the list, with any header, footer and
+// charts the format calls for, and returns the combined exit status.
+int analyze_files(const struct file_list *files, const struct arguments *arguments, double reference_entropy) {
+    size_t fileCount = files->count;
+    size_t skipped = 0;
+    struct timespec start;
+
+    clock_gettime(CLOCK_MONOTONIC, &start);
+
+    if (arguments->label && !arguments->entropy_only) {
+        if (arguments->format == FORMAT_TEXT || arguments->format == FORMAT_COMPACT) {
+            printf("Run: %s at %s\n", arguments->label, arguments->timestamp);
+        } else if (arguments->format == FORMAT_MARKDOWN) {
+            printf("**");
+            print_markdown_text(arguments->label);
+            printf("** (%s)\n\n", arguments->timestamp);
+        }
+    }
+    if (arguments->format == FORMAT_MARKDOWN) {
+        print_markdown_header(arguments);
+    } else if (arguments->format == FORMAT_HTML) {
+        print_html_header(arguments);
+    }
+
+    // Results are kept for --sort and --entropy-histogram. Without --sort each
+    // file's results are printed as soon as they are ready, so ndjson can still
+    // be followed. Compact output waits for every result to size its columns.
+    int deferred = arguments->sort != SORT_NONE || arguments->format == FORMAT_COMPACT;
+    struct result_list list = {0};
+    int status = EXIT_OK;
+    for (size_t i = 0; i < fileCount; i++) {
+        size_t firstResult = list.count;
+        int fileStatus;
+
+        if (arguments->archives && is_archive(files->paths[i])) {
+            fileStatus = analyze_archive(files->paths[i], arguments, reference_entropy, &list);
+        } else if (!within_size_limits(files->paths[i], arguments)) {
+            skipped++;
+            continue;
+        } else {
+            struct file_analysis *analysis = add_result(&list);
+            if (!analysis) {
+                fprintf(stderr, "Out of memory\n");
+                return EXIT_FILE_ERROR;
+            }
+            fileStatus = calculate_entropy(files->paths[i], arguments, reference_entropy, analysis);
+        }
+
+        if (fileStatus == EXIT_FILE_ERROR || (fileStatus == EXIT_THRESHOLD && status == EXIT_OK)) {
+            status = fileStatus;
+        }
+        if (!deferred) {
+            for (size_t j = firstResult; j < list.count; j++) {
+                print_result(&list.items[j], arguments);
+            }
+        }
+
+        // The results so far, including the failure, are still printed below.
+        if (fileStatus == EXIT_FILE_ERROR && arguments->fail_fast) {
+            break;
+        }
+    }
+
+    const struct file_analysis *results = list.items;
+    size_t resultCount = list.count;
+    const struct file_analysis **sorted = malloc((resultCount + 1) * sizeof(*sorted));
+    if (!sorted) {
+        fprintf(stderr, "Out of memory\n");
+        return EXIT_FILE_ERROR;
+    }
+    for (size_t i = 0; i < resultCount; i++) {
+        sorted[i] = &results[i];
+    }
+    const struct file_analysis **printed = sorted;
+    size_t printedCount = resultCount;
+
+    if (arguments->sort != SORT_NONE) {
+        sort_results(sorted, resultCount, arguments);
+
+        // --top and --bottom only trim the analyzed files, which sort first;
+        // failures are always reported. The histogram still covers every file.
+        size_t analyzed = 0;
+        while (analyzed < resultCount && !sorted[analyzed]->error[0]) {
+            analyzed++;
+        }
+        size_t first = 0;
+        size_t last = analyzed;
+        if (arguments->top && arguments->top < analyzed) {
+            last = arguments->top;
+        }
+        if (arguments->bottom && arguments->bottom < analyzed) {
+            first = analyzed - arguments->bottom;
+        }
+
+        // Close the gap left by --top or --bottom so the printed results are contiguous.
+        memmove(sorted + last, sorted + analyzed, (resultCount - analyzed) * sizeof(*sorted));
+        printed = sorted + first;
+        printedCount = last - first + resultCount - analyzed;
+    }
+
+    if (deferred) {
+        if (arguments->format == FORMAT_COMPACT) {
+            print_compact(printed, printedCount, arguments);
+        } else {
+            for (size_t i = 0; i < printedCount; i++) {
+                print_result(printed[i], arguments);
+            }
+        }
+    }
+
+    if (arguments->format == FORMAT_HTML) {
+        print_html_footer(results, resultCount, arguments);
+    }
+
+    if (arguments->histogram_svg && resultCount == 1 && !results[0].error[0]) {
+        FILE *svg = fopen(arguments->histogram_svg, "w");
+        if (svg) {
+            render_histogram_svg(svg, &results[0], arguments->histogram_bins, arguments->histogram_log);
+        }
+        if (!svg || fclose(svg) != 0) {
+            fprintf(stderr, "Error writing histogram: %s: %s\n", arguments->histogram_svg, strerror(errno));
+            status = EXIT_FILE_ERROR;
+        }
+    }
+
+    if (arguments->entropy_histogram_bins && fileCount > 1) {
+        print_entropy_histogram(results, resultCount, arguments->entropy_histogram_bins, arguments->color);
+    }
+    if (arguments->clusters && fileCount > 1) {
+        print_entropy_clusters(results, resultCount, arguments->clusters, arguments->bit_level);
+    }
+    if (arguments->distribution_diff_bins) {
+        if (resultCount == 2 && !results[0].error[0] && !results[1].error[0]) {
+            print_distribution_diff(&results[0], &results[1], arguments->distribution_diff_bins);
+        } else {
+            log_warn("Warning: --distribution-diff needs exactly two analyzed files, got %zu", resultCount);
+        }
+    }
+
+    if (arguments->run_summary && resultCount + skipped > 1) {
+        print_run_summary(results, resultCount, skipped, elapsed_ms(&start));
+    }
+    free(sorted);
+    for (size_t i = 0; i < resultCount; i++) {
+        free(list.items[i].owned_filename);
+        free(list.items[i].digram_cells);
+    }
+    free(list.items);
+    return status;
+}
+
+// Lists the files a run would analyze, after --min-size and --max-size, with
+// their sizes and a total, without reading them. Inputs that cannot be sized
+// here (stdin, devices, missing files) show no size and are left out of the total.
+void print_dry_run(const struct file_list *files, const struct arguments *arguments) {
+    size_t listed = 0;
+    uint64_t totalBytes = 0;
+
+    if (arguments->format == FORMAT_MARKDOWN) {
+        printf("| File | Size (bytes) |\n|---|---|\n");
+    }
+    for (size_t i = 0; i < files->count; i++) {
+        const char *path = files->paths[i];
+        if (!within_size_limits(path, arguments)) {
+            continue;
+        }
+        struct stat info;
+        int sized = strcmp(path, "-") != 0 && stat(path, &info) == 0 && S_ISREG(info.st_mode);
+        listed++;
+        totalBytes += sized ? (uint64_t)info.st_size : 0;
+
+        if (arguments->format == FORMAT_NDJSON) {
+            printf("{\"filename\":");
+            print_json_string(path);
+            if (sized) {
+                printf(",\"size_bytes\":%" PRIu64 "}\n", (uint64_t)info.st_size);
+            } else {
+                printf(",\"size_bytes\":null}\n");
+            }
+        } else if (arguments->format == FORMAT_MARKDOWN) {
+            printf("| ");
+            print_markdown_text(path);
+            if (sized) {
+                printf(" | %" PRIu64 " |\n", (uint64_t)info.st_size);
+            } else {
+                printf(" | - |\n");
+            }
+        } else if (sized) {
+            printf("%12" PRIu64 "  %s\n", (uint64_t)info.st_size, path);
+        } else {
+            printf("%12s  %s\n", "-", path);
+        }
+    }
+
+    if (arguments->format != FORMAT_NDJSON) {
+        printf("%s%zu file%s, %" PRIu64 " bytes in total\n", arguments->format == FORMAT_MARKDOWN ? "\n" : "",
+            listed, listed == 1 ? "" : "s", totalBytes);
+    }
+}
+
+// Watches the directories holding the files rather than the files themselves,
+// since editors and build tools often replace a file instead of rewriting it,
+// and a deleted file may come back. watches receives one descriptor per file.
+int start_watch(const struct file_list *files, int *watches) {
+    int fd = inotify_init1(IN_CLOEXEC);
+    if (fd < 0) {
+        return -1;
+    }
+
+    for (size_t i = 0; i < files->count; i++) {
+        const char *slash = strrchr(files->paths[i], '/');
+        char *directory = slash ? strndup(files->paths[i], slash == files->paths[i] ? 1 : slash - files->paths[i]) : strdup(".");
+        if (!directory) {
+            close(fd);
+            return -1;
+        }
+        watches[i] = inotify_add_watch(fd, directory, IN_CLOSE_WRITE | IN_MOVED_TO | IN_MOVED_FROM | IN_CREATE | IN_DELETE);
+        free(directory);
+        if (watches[i] < 0) {
+            close(fd);
+            return -1;
+        }
+    }
+    return fd;
+}
+
+// Returns true when any event in the buffer concerns one of the files.
+static int is_watched_change(const char *events, ssize_t length, const struct file_list *files, const int *watches) {
+    for (const char *p = events; p < events + length;) {
+        const struct inotify_event *event = (const struct inotify_event *)p;
+        for (size_t i = 0; event->len && i < files->count; i++) {
+            const char *slash = strrchr(files->paths[i], '/');
+            const char *name = slash ? slash + 1 : files->paths[i];
+            if (event->wd == watches[i] && strcmp(event->name, name) == 0) {
+                return 1;
+            }
+        }
+        p += sizeof(struct inotify_event) + event->len;
+    }
+    return 0;
+}
+
+// Blocks until one of the files changes, then waits for WATCH_DEBOUNCE_MS of
+// quiet so a burst of writes leads to a single new analysis. Returns -1 with
+// errno set on failure.
+int wait_for_change(int fd, const struct file_list *files, const int *watches) {
+    char events[4096] __attribute__((aligned(__alignof__(struct inotify_event))));
+    int changed = 0;
+
+    for (;;) {
+        struct pollfd pending = {fd, POLLIN, 0};
+        int ready = poll(&pending, 1, changed ? WATCH_DEBOUNCE_MS : -1);
+        if (ready < 0 && errno == EINTR) {
+            continue;
+        }
+        if (ready < 0) {
+            return -1;
+        }
+        if (ready == 0) {
+            return 0;
+        }
+
+        ssize_t length = read(fd, events, sizeof(events));
+        if (length < 0) {
+            return -1;
+        }
+        changed |= is_watched_change(events, length, files, watches);
+    }
+}
+
+// Appends a copy of path. Returns -1 when out of memory.
+static int append_file(struct file_list *list, const char *path) {
+    if (list->count + 1 >= list->capacity) {
+        size_t capacity = list->capacity ? list->capacity * 2 : 16;
+        char **grown = realloc(list->paths, capacity * sizeof(char *));
+        if (!grown) {
+            return -1;
+        }
+        list->paths = grown;
+        list->capacity = capacity;
+    }
+    if (!(list->paths[list->count] = strdup(path))) {
+        return -1;
+    }
+    list->paths[++list->count] = NULL;
+    return 0;
+}
+
+// Expands arguments containing *, ? or [ with glob(3), so patterns work the
+// same whether or not the shell expanded them. Other arguments are kept as
+// they are. Returns -1 when out of memory.
+int expand_file_arguments(struct file_list *list, char **args) {
+    for (int i = 0; args[i]; i++) {
+        glob_t matches = {0};
+        char **paths = &args[i];
+        size_t pathCount = 1;
+
+        if (strpbrk(args[i], "*?[")) {
+            int result = glob(args[i], 0, NULL, &matches);
+            if (result == GLOB_NOSPACE) {
+                globfree(&matches);
+                return -1;
+            }
+            if (result != 0) {
+                log_warn("Warning: %s matched no files", args[i]);
+                globfree(&matches);
+                continue;
+            }
+            paths = matches.gl_pathv;
+            pathCount = matches.gl_pathc;
+        }
+
+        for (size_t j = 0; j < pathCount; j++) {
+            if (append_file(list, paths[j]) != 0) {
+                globfree(&matches);
+                return -1;
+            }
+        }
+        globfree(&matches);
+    }
+    return 0;
+}
+
+// Appends the paths listed one per line in path, or stdin for "-". Paths are
+// taken literally; blank lines and lines starting with # are skipped.
+// Returns -1 with errno set on failure.
+int read_file_list(struct file_list *list, const char *path) {
+    FILE *file = strcmp(path, "-") == 0 ? stdin : fopen(path, "r");
+    if (!file) {
+        return -1;
+    }
+
+    char *line = NULL;
+    size_t lineCapacity = 0;
+    ssize_t length;
+    int result = 0;
+    while ((length = getline(&line, &lineCapacity, file)) != -1) {
+        while (length > 0 && (line[length - 1] == '\n' || line[length - 1] == '\r')) {
+            line[--length] = '\0';
+        }
+        if (length == 0 || line[0] == '#') {
+            continue;
+        }
+        if (append_file(list, line) != 0) {
+            result = -1;
+            break;
+        }
+    }
+    if (result == 0 && ferror(file)) {
+        result = -1;
+    }
+
+    int savedErrno = errno;
+    free(line);
+    close_input(file);
+    errno = savedErrno;
+    return result;
+}
+
+static int compare_paths(const void *a, const void *b) {
+    return strcmp(*(char *const *)a, *(char *const *)b);
+}
+
+// Sorts by strcmp rather than the locale's collation, so every machine gets the same order.
+void sort_file_list(struct file_list *list) {
+    qsort(list->paths, list->count, sizeof(*list->paths), compare_paths);
+}
+
+void free_file_list(struct file_list *list) {
+    for (size_t i = 0; i < list->count; i++) {
+        free(list->paths[i]);
+    }
+    free(list->paths);
+}
+
+// Tells whether a regular file's size is inside --min-size and --max-size.
+// Anything that cannot be sized here (stdin, missing files, devices) is let
+// through so analyze_file can report on it as usual.
+int within_size_limits(const char *filename, const struct arguments *arguments) {
+    struct stat info;
+    if (strcmp(filename, "-") == 0 || stat(filename, &info) != 0 || !S_ISREG(info.st_mode)) {
+        return 1;
+    }
+    return within_size_range(filename, info.st_size, arguments);
+}
+
+int within_size_range(const char *filename, uint64_t size, const struct arguments *arguments) {
+    if (size < arguments->min_size || size > arguments->max_size) {
+        log_info("Skipping %s: %" PRIu64 " bytes is outside the size limits", filename, size);
+        return 0;
+    }
+    return 1;
+}
+
+struct file_analysis *add_result(struct result_list *results) {
+    if (results->count == results->capacity) {
+        size_t capacity = results->capacity ? results->capacity * 2 : 16;
+        struct file_analysis *grown = realloc(results->items, capacity * sizeof(*grown));
+        if (!grown) {
+            return NULL;
+        }
+        results->items = grown;
+        results->capacity = capacity;
+    }
+    struct file_analysis *analysis = &results->items[results->count++];
+    memset(analysis, 0, sizeof(*analysis));
+    return analysis;
+}
+
+static int has_suffix(const char *name, const char *suffix) {
+    size_t nameLength = strlen(name);
+    size_t suffixLength = strlen(suffix);
+    return nameLength >= suffixLength && strcasecmp(name + nameLength - suffixLength, suffix) == 0;
+}
+
+int is_archive(const char *filename) {
+    return has_suffix(filename, ".zip") || has_suffix(filename, ".tar")
+        || has_suffix(filename, ".tar.gz") || has_suffix(filename, ".tgz");
+}
+
+// Records an error for the archive as a whole, e.g. when it cannot be opened or is corrupt.
+static int archive_error(struct result_list *results, const char *filename, enum error_kind kind, const char *format, ...) {
+    struct file_analysis *analysis = add_result(results);
+    if (!analysis) {
+        fprintf(stderr, "Out of memory\n");
+        return EXIT_FILE_ERROR;
+    }
+    analysis->filename = filename;
+    analysis->error_kind = kind;
+
+    va_list args;
+    va_start(args, format);
+    vsnprintf(analysis->error, sizeof(analysis->error), format, args);
+    va_end(args);
+    return EXIT_FILE_ERROR;
+}
+
+// Counts one archive member that has been read into memory, taking ownership of
+// label and data. A non-NULL error records why the member could not be read.
+static int analyze_entry(char *label, uint8_t *data, size_t length, enum error_kind error_kind, const char *error,
+    const struct arguments *arguments, double reference_entropy, const struct timespec *start, struct result_list *results) {
+    struct file_analysis *analysis = add_result(results);
+    if (!analysis) {
+        fprintf(stderr, "Out of memory\n");
+        free(label);
+        free(data);
+        return EXIT_FILE_ERROR;
+    }
+    analysis->owned_filename = label;
+    analysis->filename = label;
+
+    if (error) {
+        set_error(analysis, error_kind, "%s", error);
+    } else if (length == 0) {
+        set_error(analysis, ERROR_EMPTY, "Error: %s is empty, its entropy is undefined", label);
+    } else {
+        for (size_t i = 0; i < length; i++) {
+            analysis->counts[data[i]]++;
+        }
+        analysis->size = length;
+        analysis->symbols = length;
+        analysis->magic_length = length < MAGIC_LENGTH ? length : MAGIC_LENGTH;
+        memcpy(analysis->magic, data, analysis->magic_length);
+        if (needs_data(arguments)) {
+            analysis->data = data;
+            analysis->data_length = length;
+            data = NULL;
+        }
+    }
+    free(data);
+    return finish_entropy(analysis, arguments, reference_entropy, start);
+}
+
+static void merge_status(int *status, int entryStatus) {
+    if (entryStatus == EXIT_FILE_ERROR || (entryStatus == EXIT_THRESHOLD && *status == EXIT_OK)) {
+        *status = entryStatus;
+    }
+}
+
+// gzread takes an int-sized length, so large members are read in pieces.
+static int read_archive(gzFile archive, uint8_t *buffer, uint64_t length) {
+    while (length > 0) {
+        unsigned piece = length < (1u << 30) ? (unsigned)length : (1u << 30);
+        int bytesRead = gzread(archive, buffer, piece);
+        if (bytesRead <= 0) {
+            return -1;
+        }
+        buffer += bytesRead;
+        length -= bytesRead;
+    }
+    return 0;
+}
+
+// Tar numbers are octal text, or big-endian binary with the top bit set for
+// values too large for the field (a GNU extension).
+static uint64_t parse_tar_number(const uint8_t *field, size_t width) {
+    uint64_t value = 0;
+    if (field[0] & 0x80) {
+        value = field[0] & 0x7f;
+        for (size_t i = 1; i < width; i++) {
+            value = (value << 8) | field[i];
+        }
+        return value;
+    }
+    for (size_t i = 0; i < width && field[i]; i++) {
+        if (field[i] >= '0' && field[i] <= '7') {
+            value = value * 8 + (field[i] - '0');
+        }
+    }
+    return value;
+}
+
+// The checksum is the sum of the header bytes with the checksum field itself
+// counted as spaces.
+static int tar_checksum_matches(const uint8_t header[512]) {
+    uint64_t sum = 0;
+    for (int i = 0; i < 512; i++) {
+        sum += i >= 148 && i < 156 ? ' ' : header[i];
+    }
+    return sum == parse_tar_number(header + 148, 8);
+}
+
+// Analyzes each regular file in a tar archive, optionally gzip-compressed.
+static int analyze_tar(const char *filename, const struct arguments *arguments, double reference_entropy, struct result_list *results) {
+    gzFile archive = gzopen(filename, "rb");
+    if (!archive) {
+        return archive_error(results, filename, ERROR_IO, "Error opening file: %s: %s", filename, strerror(errno));
+    }
+
+    int status = EXIT_OK;
+    char *longName = NULL;
+    uint8_t header[512];
+    for (;;) {
+        int bytesRead = gzread(archive, header, sizeof(header));
+        if (bytesRead == 0) {
+            break;
+        }
+        if (bytesRead != (int)sizeof(header)) {
+            status = archive_error(results, filename, ERROR_ARCHIVE, "Error: %s is a truncated tar archive", filename);
+            break;
+        }
+
+        int zeroBlock = 1;
+        for (size_t i = 0; i < sizeof(header) && zeroBlock; i++) {
+            zeroBlock = header[i] == 0;
+        }
+        if (zeroBlock) {
+            break;
+        }
+        if (!tar_checksum_matches(header)) {
+            status = archive_error(results, filename, ERROR_ARCHIVE, "Error: %s is not a tar archive or has a corrupt header", filename);
+            break;
+        }
+
+        uint64_t size = parse_tar_number(header + 124, 12);
+        uint64_t padding = (512 - size % 512) % 512;
+        char type = header[156];
+
+        if (type == 'L') {
+            // GNU long name: the member's data is the name of the next header.
+            free(longName);
+            longName = size < SIZE_MAX ? malloc(size + 1) : NULL;
+            if (!longName || read_archive(archive, (uint8_t *)longName, size) != 0 || gzseek(archive, padding, SEEK_CUR) < 0) {
+                status = archive_error(results, filename, ERROR_ARCHIVE, "Error: %s is a truncated tar archive", filename);
+                break;
+            }
+            longName[size] = '\0';
+            continue;
+        }
+
+        char *label = NULL;
+        if (longName) {
+            if (asprintf(&label, "%s!%s", filename, longName) < 0) {
+                label = NULL;
+            }
+            free(longName);
+            longName = NULL;
+        } else if (memcmp(header + 257, "ustar", 5) == 0 && header[345]) {
+            if (asprintf(&label, "%s!%.155s/%.100s", filename, (char *)header + 345, (char *)header) < 0) {
+                label = NULL;
+            }
+        } else if (asprintf(&label, "%s!%.100s", filename, (char *)header) < 0) {
+            label = NULL;
+        }
+        if (!label) {
+            fprintf(stderr, "Out of memory\n");
+            status = EXIT_FILE_ERROR;
+            break;
+        }
+
+        // Directories, links and devices have no contents of their own.
+        int regular = type == '0' || type == '\0' || type == '7';
+        if (!regular || !within_size_range(label, size, arguments)) {
+            free(label);
+            if (gzseek(archive, size + padding, SEEK_CUR) < 0) {
+                status = archive_error(results, filename, ERROR_ARCHIVE, "Error: %s is a truncated tar archive", filename);
+                break;
+            }
+            continue;
+        }
+
+        struct timespec start;
+        clock_gettime(CLOCK_MONOTONIC, &start);
+        uint8_t *data = size < SIZE_MAX ? malloc(size ? size : 1) : NULL;
+        char error[256];
+        const char *entryError = NULL;
+        enum error_kind errorKind = ERROR_NONE;
+        int truncated = 0;
+        if (!data) {
+            snprintf(error, sizeof(error), "Out of memory reading file: %s", label);
+            entryError = error;
+            errorKind = ERROR_MEMORY;
+            gzseek(archive, size, SEEK_CUR);
+        } else if (read_archive(archive, data, size) != 0) {
+            snprintf(error, sizeof(error), "Error: %s is truncated", label);
+            entryError = error;
+            errorKind = ERROR_ARCHIVE;
+            truncated = 1;
+        }
+        merge_status(&status, analyze_entry(label, data, size, errorKind, entryError, arguments, reference_entropy, &start, results));
+        if (truncated || gzseek(archive, padding, SEEK_CUR) < 0) {
+            break;
+        }
+        if (status == EXIT_FILE_ERROR && arguments->fail_fast) {
+            break;
+        }
+    }
+
+    free(longName);
+    gzclose(archive);
+    return status;
+}
+
+static uint16_t read_le16(const uint8_t *bytes) {
+    return bytes[0] | bytes[1] << 8;
+}
+
+static uint32_t read_le32(const uint8_t *bytes) {
+    return (uint32_t)bytes[0] | (uint32_t)bytes[1] << 8 | (uint32_t)bytes[2] << 16 | (uint32_t)bytes[3] << 24;
+}
+
+// Inflates a raw deflate stream, as stored in zip members, into exactly out_length bytes.
+static int inflate_member(const uint8_t *in, size_t in_length, uint8_t *out, size_t out_length) {
+    z_stream stream;
+    memset(&stream, 0, sizeof(stream));
+    if (inflateInit2(&stream, -MAX_WBITS) != Z_OK) {
+        return -1;
+    }
+    stream.next_in = (Bytef *)in;
+    stream.avail_in = in_length;
+    stream.next_out = out;
+    stream.avail_out = out_length;
+    int result = inflate(&stream, Z_FINISH);
+    size_t produced = stream.total_out;
+    inflateEnd(&stream);
+    return result == Z_STREAM_END && produced == out_length ? 0 : -1;
+}
+
+// Reads one zip member into a buffer of its uncompressed size, or explains in
+// kind and error why it cannot be.
+static uint8_t *read_zip_member(FILE *file, const uint8_t *entry, const char *label, enum error_kind *kind, char *error, size_t error_size) {
+    uint16_t flags = read_le16(entry + 8);
+    uint16_t method = read_le16(entry + 10);
+    uint32_t compressedSize = read_le32(entry + 20);
+    uint32_t size = read_le32(entry + 24);
+    uint32_t localOffset = read_le32(entry + 42);
+
+    *kind = ERROR_ARCHIVE;
+    if (flags & 1) {
+        snprintf(error, error_size, "Error: %s is encrypted", label);
+        return NULL;
+    }
+    if (method != 0 && method != 8) {
+        snprintf(error, error_size, "Error: %s uses unsupported compression method %u", label, method);
+        return NULL;
+    }
+    if (compressedSize == UINT32_MAX || size == UINT32_MAX || localOffset == UINT32_MAX) {
+        snprintf(error, error_size, "Error: %s is a ZIP64 member, which is not supported", label);
+        return NULL;
+    }
+
+    uint8_t local[30];
+    if (fseeko(file, localOffset, SEEK_SET) != 0 || fread(local, 1, sizeof(local), file) != sizeof(local)
+        || read_le32(local) != 0x04034b50
+        || fseeko(file, read_le16(local + 26) + read_le16(local + 28), SEEK_CUR) != 0) {
+        snprintf(error, error_size, "Error: %s has a corrupt local header", label);
+        return NULL;
+    }
+
+    uint8_t *compressed = malloc(compressedSize ? compressedSize : 1);
+    uint8_t *data = method == 0 ? compressed : malloc(size ? size : 1);
+    if (!compressed || !data) {
+        *kind = ERROR_MEMORY;
+        snprintf(error, error_size, "Out of memory reading file: %s", label);
+    } else if (fread(compressed, 1, compressedSize, file) != compressedSize) {
+        snprintf(error, error_size, "Error: %s is truncated", label);
+    } else if (method == 0 && compressedSize != size) {
+        snprintf(error, error_size, "Error: %s has mismatched stored sizes", label);
+    } else if (method == 8 && inflate_member(compressed, compressedSize, data, size) != 0) {
+        snprintf(error, error_size, "Error: %s has corrupt compressed data", label);
+    } else {
+        if (data != compressed) {
+            free(compressed);
+        }
+        return data;
+    }
+    if (data != compressed) {
+        free(data);
+    }
+    free(compressed);
+    return NULL;
+}
+
+// Analyzes each file in a zip archive, walking the central directory at its end.
+static int analyze_zip(const char *filename, const struct arguments *arguments, double reference_entropy, struct result_list *results) {
+    FILE *file = fopen(filename, "rb");
+    if (!file) {
+        return archive_error(results, filename, ERROR_IO, "Error opening file: %s: %s", filename, strerror(errno));
+    }
+
+    // The end of central directory record is 22 bytes plus a comment of up to 64 KiB.
+    uint8_t *tail = NULL;
+    off_t fileSize = fseeko(file, 0, SEEK_END) == 0 ? ftello(file) : -1;
+    size_t tailLength = fileSize < 0 ? 0 : fileSize < 22 + 65535 ? (size_t)fileSize : 22 + 65535;
+    if (fileSize >= 22) {
+        tail = malloc(tailLength);
+    }
+    if (!tail || fseeko(file, fileSize - tailLength, SEEK_SET) != 0 || fread(tail, 1, tailLength, file) != tailLength) {
+        free(tail);
+        fclose(file);
+        return archive_error(results, filename, ERROR_ARCHIVE, "Error: %s is not a zip archive", filename);
+    }
+
+    const uint8_t *end = NULL;
+    for (size_t i = tailLength - 22 + 1; i-- > 0;) {
+        if (read_le32(tail + i) == 0x06054b50) {
+            end = tail + i;
+            break;
+        }
+    }
+    if (!end) {
+        free(tail);
+        fclose(file);
+        return archive_error(results, filename, ERROR_ARCHIVE, "Error: %s is not a zip archive", filename);
+    }
+
+    uint16_t entryCount = read_le16(end + 10);
+    uint32_t directorySize = read_le32(end + 12);
+    uint32_t directoryOffset = read_le32(end + 16);
+    free(tail);
+    if (entryCount == UINT16_MAX || directoryOffset == UINT32_MAX) {
+        fclose(file);
+        return archive_error(results, filename, ERROR_ARCHIVE, "Error: %s is a ZIP64 archive, which is not supported", filename);
+    }
+
+    uint8_t *directory = malloc(directorySize ? directorySize : 1);
+    if (!directory || fseeko(file, directoryOffset, SEEK_SET) != 0 || fread(directory, 1, directorySize, file) != directorySize) {
+        free(directory);
+        fclose(file);
+        return archive_error(results, filename, ERROR_ARCHIVE, "Error: %s has a corrupt central directory", filename);
+    }
+
+    int status = EXIT_OK;
+    size_t position = 0;
+    for (uint16_t i = 0; i < entryCount; i++) {
+        const uint8_t *entry = directory + position;
+        if (position + 46 > directorySize || read_le32(entry) != 0x02014b50
+            || position + 46 + read_le16(entry + 28) > directorySize) {
+            merge_status(&status, archive_error(results, filename, ERROR_ARCHIVE, "Error: %s has a corrupt central directory", filename));
+            break;
+        }
+        uint16_t nameLength = read_le16(entry + 28);
+        const char *name = (const char *)entry + 46;
+        position += 46 + nameLength + read_le16(entry + 30) + read_le16(entry + 32);
+
+        if (nameLength > 0 && name[nameLength - 1] == '/') {
+            continue;
+        }
+        char *label;
+        if (asprintf(&label, "%s!%.*s", filename, (int)nameLength, name) < 0) {
+            fprintf(stderr, "Out of memory\n");
+            status = EXIT_FILE_ERROR;
+            break;
+        }
+        uint32_t size = read_le32(entry + 24);
+        if (!within_size_range(label, size, arguments)) {
+            free(label);
+            continue;
+        }
+
+        struct timespec start;
+        clock_gettime(CLOCK_MONOTONIC, &start);
+        char error[256];
+        enum error_kind errorKind;
+        uint8_t *data = read_zip_member(file, entry, label, &errorKind, error, sizeof(error));
+        merge_status(&status, analyze_entry(label, data, size, errorKind, data ? NULL : error, arguments, reference_entropy, &start, results));
+        if (status == EXIT_FILE_ERROR && arguments->fail_fast) {
+            break;
+        }
+    }
+
+    free(directory);
+    fclose(file);
+    return status;
+}
+
+// Analyzes every member of a zip or tar archive as its own result, labelled
+// "archive!member". Members are read into memory; nested archives are not expanded.
+int analyze_archive(const char *filename, const struct arguments *arguments, double reference_entropy, struct result_list *results) {
+    if (has_suffix(filename, ".zip")) {
+        return analyze_zip(filename, arguments, reference_entropy, results);
+    }
+    return analyze_tar(filename, arguments, reference_entropy, results);
+}
+
+static size_t hash_path(const char *path) {
+    size_t hash = 14695981039346656037u;    // 64-bit FNV-1a
+    for (; *path; path++) {
+        hash = (hash ^ (unsigned char)*path) * 1099511628211u;
+    }
+    return hash;
+}
+
+// Returns the slot for path: the one holding its entry, or the empty slot where it would go.
+static size_t *find_cache_slot(const struct analysis_cache *cache, const char *path) {
+    size_t mask = cache->slot_count - 1;
+    for (size_t i = hash_path(path) & mask;; i = (i + 1) & mask) {
+        size_t *slot = &cache->slots[i];
+        if (*slot == 0 || strcmp(cache->entries[*slot - 1].path, path) == 0) {
+            return slot;
+        }
+    }
+}
+
+static struct cache_entry *find_cache_entry(const struct analysis_cache *cache, const char *path) {
+    if (cache->slot_count == 0) {
+        return NULL;
+    }
+    size_t *slot = find_cache_slot(cache, path);
+    return *slot ? &cache->entries[*slot - 1] : NULL;
+}
+
+// Adds an entry, taking ownership of its path, or replaces the entry for the
+// same path. Keeps the table at most half full. Returns -1 when out of memory.
+static int put_cache_entry(struct analysis_cache *cache, const struct cache_entry *entry) {
+    if (2 * (cache->count + 1) > cache->slot_count) {
+        size_t slotCount = cache->slot_count ? cache->slot_count * 2 : 64;
+        size_t *slots = calloc(slotCount, sizeof(*slots));
+        if (!slots) {
+            return -1;
+        }
+        free(cache->slots);
+        cache->slots = slots;
+        cache->slot_count = slotCount;
+        for (size_t i = 0; i < cache->count; i++) {
+            *find_cache_slot(cache, cache->entries[i].path) = i + 1;
+        }
+    }
+
+    size_t *slot = find_cache_slot(cache, entry->path);
+    if (*slot) {
+        struct cache_entry *existing = &cache->entries[*slot - 1];
+        free(existing->path);
+        *existing = *entry;
+        return 0;
+    }
+    if (cache->count == cache->capacity) {
+        size_t capacity = cache->capacity ? cache->capacity * 2 : 64;
+        struct cache_entry *grown = realloc(cache->entries, capacity * sizeof(*grown));
+        if (!grown) {
+            return -1;
+        }
+        cache->entries = grown;
+        cache->capacity = capacity;
+    }
+    cache->entries[cache->count++] = *entry;
+    *slot = cache->count;
+    return 0;
+}
+
+static int64_t modification_ns(const struct stat *info) {
+    return (int64_t)info->st_mtim.tv_sec * 1000000000 + info->st_mtim.tv_nsec;
+}
+
+// Fills analysis from the cache, as analyze_file would, when the file and the
+// counting options are the same as when it was cached. Returns 1 on a hit.
+static int load_cached_counts(struct analysis_cache *cache, const char *filename, const struct stat *info,
+    const struct arguments *arguments, struct file_analysis *analysis) {
+    const struct cache_entry *entry = find_cache_entry(cache, filename);
+    if (!entry || entry->size != (uint64_t)info->st_size || entry->mtime_ns != modification_ns(info)
+        || entry->offset != arguments->offset || entry->length != arguments->length || entry->decompress != arguments->decompress) {
+        return 0;
+    }
+
+    memset(analysis, 0, sizeof(*analysis));
+    analysis->filename = filename;
+    analysis->offset = arguments->offset;
+    analysis->ranged = arguments->offset > 0 || arguments->length != UINT64_MAX;
+    for (int i = 0; i < 256; i++) {
+        analysis->counts[i] = entry->counts[i];
+        analysis->size += entry->counts[i];
+    }
+    analysis->symbols = analysis->size;
+    analysis->magic_length = entry->magic_length;
+    memcpy(analysis->magic, entry->magic, entry->magic_length);
+    log_info("%s: using cached byte counts", filename);
+    return 1;
+}
+
+// Records the counts of a freshly analyzed file. Running out of memory only
+// means the file is not cached.
+static void store_cached_counts(struct analysis_cache *cache, const char *filename, const struct stat *info,
+    const struct arguments *arguments, const struct file_analysis *analysis) {
+    struct cache_entry entry = {
+        .path = strdup(filename),
+        .size = info->st_size,
+        .mtime_ns = modification_ns(info),
+        .offset = arguments->offset,
+        .length = arguments->length,
+        .decompress = arguments->decompress,
+        .magic_length = analysis->magic_length,
+    };
+    memcpy(entry.magic, analysis->magic, analysis->magic_length);
+    memcpy(entry.counts, analysis->counts, sizeof(entry.counts));
+    if (!entry.path || put_cache_entry(cache, &entry) != 0) {
+        free(entry.path);
+        return;
+    }
+    cache->dirty = 1;
+}
+
+// Parses a JSON string at *text written by write_json_string, advancing past it.
+static char *parse_json_string(const char **text) {
+    const char *p = *text;
+    if (*p++ != '"') {
+        return NULL;
+    }
+    char *value = malloc(strlen(p) + 1);
+    char *out = value;
+    while (value && *p && *p != '"') {
+        if (*p != '\\') {
+            *out++ = *p++;
+            continue;
+        }
+        p++;
+        if (*p == 'n') {
+            *out++ = '\n';
+        } else if (*p == 't') {
+            *out++ = '\t';
+        } else if (*p == 'u' && hex_digit(p[1]) == 0 && hex_digit(p[2]) == 0 && hex_digit(p[3]) >= 0 && hex_digit(p[4]) >= 0) {
+            // Only control characters are written as \u escapes.
+            *out++ = hex_digit(p[3]) << 4 | hex_digit(p[4]);
+            p += 4;
+        } else if (*p == '"' || *p == '\\') {
+            *out++ = *p;
+        } else {
+            break;
+        }
+        p++;
+    }
+    if (!value || *p != '"') {
+        free(value);
+        return NULL;
+    }
+    *out = '\0';
+    *text = p + 1;
+    return value;
+}
+
+// Expects the literal prefix at *text and parses the unsigned number after it.
+static int parse_cache_field(const char **text, const char *prefix, uint64_t *value) {
+    size_t length = strlen(prefix);
+    if (strncmp(*text, prefix, length) != 0 || (*text)[length] < '0' || (*text)[length] > '9') {
+        return -1;
+    }
+    char *end;
+    errno = 0;
+    *value = strtoull(*text + length, &end, 10);
+    *text = end;
+    return errno ? -1 : 0;
+}
+
+// Like parse_cache_field for a signed number, such as the mtime of a file
+// from before 1970.
+static int parse_cache_signed_field(const char **text, const char *prefix, int64_t *value) {
+    size_t length = strlen(prefix);
+    if (strncmp(*text, prefix, length) != 0) {
+        return -1;
+    }
+    const char *number = *text + length + ((*text)[length] == '-');
+    if (*number < '0' || *number > '9') {
+        return -1;
+    }
+    char *end;
+    errno = 0;
+    *value = strtoll(*text + length, &end, 10);
+    *text = end;
+    return errno ? -1 : 0;
+}
+
+static int parse_cache_line(const char *line, struct cache_entry *entry) {
+    const char *p = line;
+    uint64_t decompress;
+    memset(entry, 0, sizeof(*entry));
+
+    if (strncmp(p, "{\"path\":", 8) != 0) {
+        return -1;
+    }
+    p += 8;
+    if (!(entry->path = parse_json_string(&p))) {
+        return -1;
+    }
+    if (parse_cache_field(&p, ",\"size\":", &entry->size) != 0 || parse_cache_signed_field(&p, ",\"mtime_ns\":", &entry->mtime_ns) != 0
+        || parse_cache_field(&p, ",\"offset\":", &entry->offset) != 0 || parse_cache_field(&p, ",\"length\":", &entry->length) != 0
+        || parse_cache_field(&p, ",\"decompress\":", &decompress) != 0 || strncmp(p, ",\"magic\":\"", 10) != 0) {
+        free(entry->path);
+        return -1;
+    }
+    entry->decompress = decompress != 0;
+
+    p += 10;
+    while (entry->magic_length < MAGIC_LENGTH && hex_digit(p[0]) >= 0 && hex_digit(p[1]) >= 0) {
+        entry->magic[entry->magic_length++] = hex_digit(p[0]) << 4 | hex_digit(p[1]);
+        p += 2;
+    }
+    for (int i = 0; i < 256; i++) {
+        if (parse_cache_field(&p, i == 0 ? "\",\"counts\":[" : ",", &entry->counts[i]) != 0) {
+            free(entry->path);
+            return -1;
+        }
+    }
+    if (strcmp(p, "]}") != 0) {
+        free(entry->path);
+        return -1;
+    }
+    return 0;
+}
+
+// Reads a cache written by save_cache: a JSON Lines file with a version line
+// and one object per file. Returns -1 with errno set when the file cannot be
+// read, or 0 in errno when it is not a cache.
+int load_cache(struct analysis_cache *cache, const char *path) {
+    FILE *file = fopen(path, "r");
+    if (!file) {
+        return -1;
+    }
+
+    char *line = NULL;
+    size_t lineCapacity = 0;
+    ssize_t length;
+    int result = 0;
+    int lineNumber = 0;
+    while ((length = getline(&line, &lineCapacity, file)) != -1) {
+        if (length > 0 && line[length - 1] == '\n') {
+            line[--length] = '\0';
+        }
+        if (lineNumber++ == 0) {
+            if (strcmp(line, "{\"entropy_cache\":1}") != 0) {
+                errno = 0;
+                result = -1;
+                break;
+            }
+            continue;
+        }
+        struct cache_entry entry;
+        if (parse_cache_line(line, &entry) != 0) {
+            errno = 0;
+            result = -1;
+            break;
+        }
+        if (put_cache_entry(cache, &entry) != 0) {
+            free(entry.path);
+            result = -1;
+            break;
+        }
+    }
+    if (result == 0 && ferror(file)) {
+        result = -1;
+    }
+
+    int savedErrno = errno;
+    free(line);
+    fclose(file);
+    errno = savedErrno;
+    return result;
+}
+
+// Writes the cache to a temporary file beside path and renames it into place,
+// so an interrupted run never leaves a half-written cache.
+int save_cache(const struct analysis_cache *cache, const char *path) {
+    char *temporary;
+    if (asprintf(&temporary, "%s.tmp", path) < 0) {
+        return -1;
+    }
+    FILE *file = fopen(temporary, "w");
+    if (!file) {
+        free(temporary);
+        return -1;
+    }
+
+    fprintf(file, "{\"entropy_cache\":1}\n");
+    for (size_t i = 0; i < cache->count; i++) {
+        const struct cache_entry *entry = &cache->entries[i];
+        fprintf(file, "{\"path\":");
+        write_json_string(file, entry->path);
+        fprintf(file, ",\"size\":%" PRIu64 ",\"mtime_ns\":%" PRId64 ",\"offset\":%" PRIu64 ",\"length\":%" PRIu64 ",\"decompress\":%d,\"magic\":\"",
+            entry->size, entry->mtime_ns, entry->offset, entry->length, entry->decompress);
+        for (size_t j = 0; j < entry->magic_length; j++) {
+            fprintf(file, "%02x", entry->magic[j]);
+        }
+        fprintf(file, "\",\"counts\":[");
+        for (int j = 0; j < 256; j++) {
+            fprintf(file, "%s%" PRIu64, j ? "," : "", entry->counts[j]);
+        }
+        fprintf(file, "]}\n");
+    }
+
+    int result = ferror(file) ? -1 : 0;
+    if (fclose(file) != 0) {
+        result = -1;
+    }
+    if (result == 0 && rename(temporary, path) != 0) {
+        result = -1;
+    }
+    if (result != 0) {
+        int savedErrno = errno;
+        unlink(temporary);
+        errno = savedErrno;
+    }
+    free(temporary);
+    return result;
+}
+
+void free_cache(struct analysis_cache *cache) {
+    for (size_t i = 0; i < cache->count; i++) {
+        free(cache->entries[i].path);
+    }
+    free(cache->entries);
+    free(cache->slots);
+}
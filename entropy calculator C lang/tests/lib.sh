@@ -0,0 +1,66 @@
+# Shared setup for the test scripts: builds main.c once into a scratch
+# directory (or uses $ENT when run.sh already built it) and provides a few
+# assertions. Each script exits non-zero if any check failed.
+set -eu
+
+tests=$(cd "$(dirname "$0")" && pwd)
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+
+if [ -z "${ENT:-}" ]; then
+    ENT="$work/main"
+    ${CC:-cc} -O2 -o "$ENT" "$tests/../main.c" -lm -pthread -lz -llzma
+fi
+
+failures=0
+
+fail() {
+    printf 'FAIL: %s: %s\n' "$(basename "$0")" "$*" >&2
+    failures=$((failures + 1))
+}
+
+# expect_eq ACTUAL EXPECTED DESCRIPTION
+expect_eq() {
+    [ "$1" = "$2" ] || fail "$3: expected '$2', got '$1'"
+}
+
+# expect_match TEXT PATTERN DESCRIPTION, PATTERN being an extended regex
+expect_match() {
+    printf '%s\n' "$1" | grep -Eq -- "$2" || fail "$3: '$2' not found in: $1"
+}
+
+# expect_status STATUS COMMAND... runs COMMAND and checks its exit status
+expect_status() {
+    expected=$1
+    shift
+    status=0
+    "$@" >/dev/null 2>&1 || status=$?
+    [ "$status" -eq "$expected" ] || fail "$*: expected exit status $expected, got $status"
+}
+
+# expect_near ACTUAL EXPECTED TOLERANCE DESCRIPTION
+expect_near() {
+    awk -v a="$1" -v e="$2" -v t="$3" 'BEGIN { d = a - e; exit !(a != "" && (d < 0 ? -d : d) <= t) }' \
+        || fail "$4: expected $2 (within $3), got '$1'"
+}
+
+# json_field LINE NAME prints the value of NAME in an ndjson LINE
+json_field() {
+    printf '%s\n' "$1" | sed -n "s/.*\"$2\":\([^,}]*\).*/\1/p"
+}
+
+# all_bytes prints each byte value 0 to 255 once, in order
+all_bytes() {
+    i=0
+    while [ $i -lt 256 ]; do
+        printf "\\$(printf %03o $i)"
+        i=$((i + 1))
+    done
+}
+
+finish() {
+    if [ "$failures" -ne 0 ]; then
+        exit 1
+    fi
+    echo "ok: $(basename "$0")"
+}
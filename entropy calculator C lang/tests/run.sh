@@ -0,0 +1,16 @@
+#!/bin/sh
+# Builds main.c once and runs every test_*.sh next to this script.
+set -eu
+
+tests=$(cd "$(dirname "$0")" && pwd)
+build=$(mktemp -d)
+trap 'rm -rf "$build"' EXIT
+
+${CC:-cc} -Wall -O2 -o "$build/main" "$tests/../main.c" -lm -pthread -lz -llzma
+export ENT="$build/main"
+
+status=0
+for test in "$tests"/test_*.sh; do
+    sh "$test" || status=1
+done
+exit $status
@@ -0,0 +1,18 @@
+#!/bin/sh
+# --bit-stream lists the bit positions from bit 0 to bit 7 in every output,
+# so index i of the ndjson array is bit i and the text line reads the same.
+. "$(dirname "$0")/lib.sh"
+
+# Only bit 0 varies: it carries 1 bit, every other position none.
+printf '\000\001\000\001\000\001\000\001' > "$work/low"
+line=$("$ENT" -f ndjson --bit-stream "$work/low")
+expect_match "$line" '"bit_position_entropy":\[1,0,0,0,0,0,0,0\]' "ndjson, bit 0 first"
+expect_match "$("$ENT" --bit-stream "$work/low")" "^Bit-position entropy \(0 to 7\) : 1\.0000 0\.0000 0\.0000 0\.0000 0\.0000 0\.0000 0\.0000 0\.0000$" "text, bit 0 first"
+
+# Only bit 7 varies.
+printf '\000\200\000\200' > "$work/high"
+line=$("$ENT" -f ndjson --bit-stream "$work/high")
+expect_match "$line" '"bit_position_entropy":\[0,0,0,0,0,0,0,1\]' "ndjson, bit 7 last"
+expect_near "$(json_field "$line" bit_stream_entropy)" 0.3372900666 1e-9 "2 ones in 32 bits"
+
+finish
@@ -0,0 +1,31 @@
+#!/bin/sh
+# --cache must round-trip files with unusual metadata: an mtime before 1970
+# (negative nanoseconds since the epoch), exactly at the epoch, and paths that
+# need escaping in JSON. A second run reuses every entry.
+. "$(dirname "$0")/lib.sh"
+
+cd "$work"
+printf 'hello world' > old
+printf 'hello world' > epoch
+printf 'abcd' > 'quote " and \ backslash'
+printf 'abab' > 'tab	and space'
+touch -d '1960-01-01 00:00:00 UTC' old
+touch -d '1970-01-01 00:00:00 UTC' epoch
+
+fresh=$("$ENT" -f ndjson old epoch 'quote " and \ backslash' 'tab	and space' 2> /dev/null)
+first=$("$ENT" -f ndjson --cache cache old epoch 'quote " and \ backslash' 'tab	and space' 2> /dev/null)
+expect_eq "$first" "$fresh" "first run with --cache"
+expect_match "$(cat cache)" '"mtime_ns":-315619200000000000,' "negative mtime is saved"
+
+status=0
+second=$("$ENT" -v -f ndjson --cache cache old epoch 'quote " and \ backslash' 'tab	and space' 2> log) || status=$?
+expect_eq "$status" 0 "second run exit status"
+expect_eq "$second" "$fresh" "second run from the cache"
+expect_eq "$(grep -c 'using cached byte counts' log)" 4 "every file comes from the cache"
+
+# Moving the old file's mtime, still before 1970, invalidates its entry only.
+touch -d '1965-06-01 00:00:00 UTC' old
+"$ENT" -v --cache cache old epoch > /dev/null 2> log
+expect_eq "$(grep -c 'using cached byte counts' log)" 1 "changed mtime is recounted"
+
+finish
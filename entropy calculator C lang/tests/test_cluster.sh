@@ -0,0 +1,26 @@
+#!/bin/sh
+# --cluster groups the files by entropy with 1-D k-means. A clearly bimodal
+# set must split into its two groups, whatever order the files come in.
+. "$(dirname "$0")/lib.sh"
+
+cd "$work"
+printf 'aaab' > low1         # 0.81 bits
+printf 'abab' > low2         # 1 bit
+printf 'abcabc' > low3       # 1.58 bits
+printf 'aabbcd' > low4       # 1.92 bits
+for i in 1 2 3 4; do
+    head -c 65536 /dev/urandom > high$i
+done
+
+clusters=$("$ENT" --cluster 2 high1 low1 high2 low2 low3 high3 low4 high4 2> /dev/null)
+expect_match "$clusters" "^--- Entropy clusters \(8 files\) ---$" "heading"
+expect_match "$clusters" "^Cluster 1: 4 files, 0\.8113 - 1\.9183 bits, centroid 1\.3286$" "low cluster"
+expect_match "$clusters" "^Cluster 2: 4 files, 7\.99[0-9]{2} - (7\.99[0-9]{2}|8\.0000) bits" "high cluster"
+
+# K above the file count leaves one cluster per file.
+clusters=$("$ENT" --cluster 3 low1 low2 2> /dev/null)
+expect_eq "$(printf '%s\n' "$clusters" | grep -c '^Cluster ')" 2 "two files, K = 3"
+
+expect_status 64 "$ENT" -f ndjson --cluster 2 low1 low2
+
+finish
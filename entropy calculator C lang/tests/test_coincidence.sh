@@ -0,0 +1,28 @@
+#!/bin/sh
+# index_of_coincidence is sum n(n-1) / N(N-1), the chance that two bytes
+# drawn without replacement match; collision_entropy is -log2(sum p^2).
+. "$(dirname "$0")/lib.sh"
+
+head -c 1048576 /dev/urandom > "$work/random"
+line=$("$ENT" -f ndjson "$work/random")
+expect_near "$(json_field "$line" index_of_coincidence)" 0.00390625 0.00001 "random data, about 1/256"
+expect_near "$(json_field "$line" collision_entropy)" 8 0.01 "random data, collision entropy"
+
+head -c 1000 /dev/zero > "$work/constant"
+line=$("$ENT" -f ndjson "$work/constant")
+expect_near "$(json_field "$line" index_of_coincidence)" 1 0 "constant file"
+expect_near "$(json_field "$line" collision_entropy)" 0 0 "constant file, collision entropy"
+
+# 'l' 3 times and 'o' twice out of 11: (6 + 2) / 110 and -log2(19 / 121).
+printf 'hello world' > "$work/text"
+line=$("$ENT" -f ndjson "$work/text")
+expect_near "$(json_field "$line" index_of_coincidence)" 0.0727272727 1e-9 "text"
+expect_near "$(json_field "$line" collision_entropy)" 2.6709357238 1e-9 "text, collision entropy"
+expect_match "$("$ENT" "$work/text")" "Index of coincidence +: 0\.072727 \(collision entropy 2\.670936 bits\)" "text output"
+
+# One byte has no pair to compare.
+printf 'x' > "$work/one"
+line=$("$ENT" -f ndjson "$work/one")
+expect_eq "$(json_field "$line" index_of_coincidence)" null "single byte"
+
+finish
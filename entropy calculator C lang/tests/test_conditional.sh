@@ -0,0 +1,42 @@
+#!/bin/sh
+# --conditional is H(X | previous byte). Knowing the previous byte can only
+# help, so it never exceeds the entropy per byte. It stays near 8 bits for
+# random data and drops to 0 when each byte follows from the one before.
+. "$(dirname "$0")/lib.sh"
+
+# 4 MiB keeps the bias of estimating 65536 transitions under 0.02 bits.
+head -c 4194304 /dev/urandom > "$work/random"
+line=$("$ENT" -f ndjson --conditional "$work/random")
+expect_near "$(json_field "$line" conditional_entropy)" 8 0.05 "random data"
+
+# Every byte value once, in order: uniform bytes, but each one predicts the next.
+all_bytes > "$work/ramp"
+line=$("$ENT" -f ndjson --conditional "$work/ramp")
+expect_near "$(json_field "$line" byte_entropy)" 8 1e-12 "ramp, entropy per byte"
+expect_near "$(json_field "$line" conditional_entropy)" 0 0 "ramp"
+
+yes abc | tr -d '\n' | head -c 3000 > "$work/pattern"
+line=$("$ENT" -f ndjson --conditional "$work/pattern")
+expect_near "$(json_field "$line" conditional_entropy)" 0 0 "repeating pattern"
+
+# The last byte counts as preceding the first, giving 11 pairs. Only 'l' (to
+# l, o, d) and 'o' (to space, r) leave a choice: 3/11 log2(3) + 2/11 bits.
+printf 'hello world' > "$work/text"
+line=$("$ENT" -f ndjson --conditional "$work/text")
+expect_near "$(json_field "$line" conditional_entropy)" 0.6140806820 1e-9 "hello world"
+
+printf 'aaaaaaabaaab' > "$work/skewed"
+head -c 3000 /dev/urandom | od -An -tx1 | tr -d ' \n' > "$work/hex"
+for file in random ramp pattern text skewed hex; do
+    line=$("$ENT" -f ndjson --conditional "$work/$file")
+    awk -v c="$(json_field "$line" conditional_entropy)" -v h="$(json_field "$line" byte_entropy)" \
+        'BEGIN { exit !(c != "" && c <= h + 1e-12) }' \
+        || fail "$file: conditional entropy is above the entropy per byte: $line"
+done
+
+# One byte has no predecessor.
+printf 'x' > "$work/one"
+line=$("$ENT" -f ndjson --conditional "$work/one")
+expect_eq "$(json_field "$line" conditional_entropy)" null "single byte"
+
+finish
@@ -0,0 +1,31 @@
+#!/bin/sh
+# --delta (alias --delta-entropy) measures the entropy of the wrapping
+# differences between consecutive bytes, which is low for smooth data even
+# when the bytes themselves look uniform.
+. "$(dirname "$0")/lib.sh"
+
+all_bytes > "$work/cycle"
+: > "$work/ramp"
+for i in 1 2 3 4 5 6 7 8; do
+    cat "$work/cycle" >> "$work/ramp"
+done
+
+# 0, 1, ..., 255, 0, 1, ...: every difference is 1, wrapping included.
+line=$("$ENT" -f ndjson --delta "$work/ramp")
+expect_near "$(json_field "$line" byte_entropy)" 8 1e-12 "ramp, raw bytes"
+expect_near "$(json_field "$line" delta_entropy)" 0 0 "ramp, differences"
+
+# a, b, a, b: differences alternate between 1 and 255.
+printf 'ababababababababa' > "$work/alternating"
+line=$("$ENT" -f ndjson --delta "$work/alternating")
+expect_near "$(json_field "$line" delta_entropy)" 1 1e-12 "alternating bytes"
+
+expect_eq "$("$ENT" --delta "$work/ramp")" "$("$ENT" --delta-entropy "$work/ramp")" "--delta is --delta-entropy"
+expect_match "$("$ENT" --delta "$work/ramp")" "Delta entropy +: 0\.000000 bits per byte \(-8\.000000 vs raw\)" "text output"
+
+# A single byte has no difference.
+printf 'x' > "$work/one"
+line=$("$ENT" -f ndjson --delta "$work/one")
+expect_eq "$(json_field "$line" delta_entropy)" null "single byte"
+
+finish
@@ -0,0 +1,32 @@
+#!/bin/sh
+# Entropy of file, delta and coding ratio are worked out once and shared by
+# every output format. These values pin them so the formats cannot drift
+# apart. An infinite coding ratio is "inf" in text and markdown and null in
+# ndjson, which has no infinity.
+. "$(dirname "$0")/lib.sh"
+
+# 'hello world': 2.845351 bits per byte over 11 bytes.
+printf 'hello world' > "$work/text"
+line=$("$ENT" -f ndjson "$work/text")
+expect_near "$(json_field "$line" entropy_of_file)" 31.2988603028 1e-9 "ndjson entropy of file"
+expect_near "$(json_field "$line" delta_bytes)" 7.0876424621 1e-9 "ndjson delta"
+expect_near "$(json_field "$line" coding_ratio)" 2.8116039737 1e-9 "ndjson coding ratio"
+
+text=$("$ENT" "$work/text")
+expect_match "$text" "^Entropy of file +: 31\.298860 bits \(3\.912358 bytes\)$" "text entropy of file"
+expect_match "$text" "^Delta +: 7\.087642 bytes " "text delta"
+expect_match "$text" "^Best Theoretical Coding ratio : 2\.811604$" "text coding ratio"
+
+markdown=$("$ENT" -f markdown "$work/text")
+expect_match "$markdown" "^\| .*text \| 11 \| 2\.845351 \| 31\.298860 \| 7\.087642 \| 2\.811604 \|" "markdown row"
+
+# A constant file: no entropy, every byte compressible, no finite ratio.
+printf 'aaaa' > "$work/constant"
+line=$("$ENT" -f ndjson "$work/constant")
+expect_near "$(json_field "$line" entropy_of_file)" 0 0 "constant, entropy of file"
+expect_near "$(json_field "$line" delta_bytes)" 4 0 "constant, delta"
+expect_eq "$(json_field "$line" coding_ratio)" null "constant, ndjson coding ratio"
+expect_match "$("$ENT" "$work/constant")" "^Best Theoretical Coding ratio : inf$" "constant, text coding ratio"
+expect_match "$("$ENT" -f markdown "$work/constant")" "^\| .*constant \| 4 \| 0\.000000 \| 0\.000000 \| 4\.000000 \| inf \|" "constant, markdown row"
+
+finish
@@ -0,0 +1,44 @@
+#!/bin/sh
+# Empty inputs and empty ranges end in a specific error with exit status 1,
+# in every output format and mode, never in a silent blank result.
+. "$(dirname "$0")/lib.sh"
+
+: > "$work/empty"
+printf 'hello world' > "$work/text"
+
+expect_status 1 "$ENT" "$work/empty"
+message=$("$ENT" "$work/empty" 2>&1 || true)
+expect_match "$message" "empty is empty, its entropy is undefined" "text output"
+
+line=$("$ENT" -f ndjson "$work/empty" || true)
+expect_eq "$(json_field "$line" error_kind)" '"empty"' "ndjson error kind"
+line=$(printf '' | "$ENT" -f ndjson - || true)
+expect_eq "$(json_field "$line" error_kind)" '"empty"' "empty stdin"
+line=$("$ENT" -f ndjson --include-histogram "$work/empty" || true)
+expect_eq "$(json_field "$line" error_kind)" '"empty"' "--include-histogram"
+line=$("$ENT" -f ndjson --sample 2 "$work/empty" || true)
+expect_eq "$(json_field "$line" error_kind)" '"empty"' "--sample"
+for option in --conditional --delta-entropy --lz --codepoint --monte-carlo; do
+    line=$("$ENT" -f ndjson $option "$work/empty" || true)
+    expect_eq "$(json_field "$line" error_kind)" '"empty"' "$option"
+done
+
+markdown=$("$ENT" -f markdown "$work/empty" || true)
+expect_match "$markdown" "^\| .*empty \| \| Error: .*is empty" "markdown row"
+expect_status 1 "$ENT" --entropy-only "$work/empty"
+
+# A range past the end of the file is a range error, not an empty result.
+line=$("$ENT" -f ndjson --offset 20 "$work/text" || true)
+expect_eq "$(json_field "$line" error_kind)" '"range"' "--offset past the end"
+
+# Next to a good file, the empty one fails alone and the run still exits 1.
+expect_status 1 "$ENT" "$work/empty" "$work/text"
+output=$("$ENT" -f ndjson "$work/empty" "$work/text" 2>/dev/null || true)
+expect_eq "$(printf '%s\n' "$output" | grep -c byte_entropy)" 1 "the good file is still reported"
+
+# Comparisons between files leave out the empty one and say so.
+message=$("$ENT" --distribution-diff=4 "$work/empty" "$work/text" 2>&1 || true)
+expect_match "$message" "needs exactly two analyzed files" "--distribution-diff"
+expect_status 1 "$ENT" --relative-to "$work/empty" "$work/text"
+
+finish
@@ -0,0 +1,29 @@
+#!/bin/sh
+# ENTROPY_* variables set option defaults: built-in defaults < environment <
+# command line. There is no config file layer.
+. "$(dirname "$0")/lib.sh"
+
+printf 'hello world' > "$work/text"
+
+# Default: text output.
+expect_match "$("$ENT" "$work/text")" "^Entropy per byte +: " "built-in default format"
+
+# The environment overrides the default.
+output=$(ENTROPY_FORMAT=ndjson "$ENT" "$work/text")
+expect_match "$output" '^\{"filename":' "ENTROPY_FORMAT=ndjson"
+
+# The command line overrides the environment.
+output=$(ENTROPY_FORMAT=ndjson "$ENT" -f markdown "$work/text")
+expect_match "$output" '^\| File \|' "-f markdown over ENTROPY_FORMAT"
+
+output=$(ENTROPY_MIN_SIZE=100 "$ENT" -q "$work/text")
+expect_eq "$output" "" "ENTROPY_MIN_SIZE skips a small file"
+output=$(ENTROPY_MIN_SIZE=100 "$ENT" --min-size 5 --entropy-only "$work/text")
+expect_eq "$output" "2.845351" "--min-size over ENTROPY_MIN_SIZE"
+
+# Values are checked like command-line ones; an empty variable is ignored.
+expect_status 64 env ENTROPY_FORMAT=bogus "$ENT" "$work/text"
+expect_status 64 env ENTROPY_THREADS=0 "$ENT" "$work/text"
+expect_status 0 env ENTROPY_FORMAT= "$ENT" "$work/text"
+
+finish
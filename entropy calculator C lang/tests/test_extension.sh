@@ -0,0 +1,19 @@
+#!/bin/sh
+# There is no --extension filter: the files are the arguments, their glob
+# matches and --files-from. This pins that --extension is rejected rather than
+# ignored, and that quoted patterns select several extensions in either case.
+. "$(dirname "$0")/lib.sh"
+
+cd "$work"
+printf 'a' > one.bin
+printf 'b' > two.DAT
+printf 'c' > three.Img
+printf 'd' > four.txt
+
+expect_status 64 "$ENT" --extension bin one.bin
+expect_status 64 "$ENT" --extension=bin,dat one.bin
+
+listed=$("$ENT" --dry-run -f ndjson '*.[bB][iI][nN]' '*.[dD][aA][tT]' '*.[iI][mM][gG]' | sed 's/.*"filename":"\([^"]*\)".*/\1/')
+expect_eq "$listed" "$(printf 'one.bin\ntwo.DAT\nthree.Img')" "patterns for .bin, .dat and .img in any case"
+
+finish
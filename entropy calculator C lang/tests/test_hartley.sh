@@ -0,0 +1,36 @@
+#!/bin/sh
+# distinct_bytes counts the byte values that occur, and hartley_entropy is
+# log2 of that: an upper bound on the entropy per byte, reached only when
+# those values are equally frequent.
+. "$(dirname "$0")/lib.sh"
+
+printf 'aaaa' > "$work/constant"
+printf 'hello world' > "$work/text"
+printf 'aaaaaaab' > "$work/skewed"
+all_bytes > "$work/uniform"
+head -c 4096 /dev/urandom > "$work/random"
+
+line=$("$ENT" -f ndjson "$work/constant")
+expect_eq "$(json_field "$line" distinct_bytes)" 1 "constant, distinct bytes"
+expect_near "$(json_field "$line" hartley_entropy)" 0 0 "constant, Hartley entropy"
+
+line=$("$ENT" -f ndjson "$work/text")
+expect_eq "$(json_field "$line" distinct_bytes)" 8 "text, distinct bytes"
+expect_near "$(json_field "$line" hartley_entropy)" 3 1e-12 "text, Hartley entropy"
+
+line=$("$ENT" -f ndjson "$work/uniform")
+expect_eq "$(json_field "$line" distinct_bytes)" 256 "uniform, distinct bytes"
+expect_near "$(json_field "$line" hartley_entropy)" 8 1e-12 "uniform, Hartley entropy"
+expect_near "$(json_field "$line" byte_entropy)" 8 1e-12 "uniform reaches the bound"
+
+for file in constant text skewed uniform random; do
+    line=$("$ENT" -f ndjson "$work/$file")
+    entropy=$(json_field "$line" byte_entropy)
+    hartley=$(json_field "$line" hartley_entropy)
+    awk -v e="$entropy" -v h="$hartley" 'BEGIN { exit !(e <= h + 1e-12) }' \
+        || fail "$file: entropy $entropy is above the Hartley entropy $hartley"
+done
+
+expect_match "$("$ENT" "$work/text")" "Distinct bytes +: 8 \(Hartley entropy 3\.000000 bits\)" "text output"
+
+finish
@@ -0,0 +1,29 @@
+#!/bin/sh
+# The byte histogram comes from the same single pass as the entropy. With
+# --cache a later run takes it from the saved counts without reading the file,
+# and must report exactly what a fresh read does.
+. "$(dirname "$0")/lib.sh"
+
+head -c 100000 /dev/urandom > "$work/data"
+printf 'hello world' >> "$work/data"
+
+fresh=$("$ENT" -f ndjson --include-histogram "$work/data")
+"$ENT" -f ndjson --include-histogram --cache "$work/cache" "$work/data" > /dev/null
+
+cached=$("$ENT" -v -f ndjson --include-histogram --cache "$work/cache" "$work/data" 2> "$work/log")
+expect_match "$(cat "$work/log")" "using cached byte counts" "second run uses the cache"
+expect_eq "$cached" "$fresh" "ndjson histogram from the cache"
+
+"$ENT" --histogram-svg "$work/fresh.svg" "$work/data" > /dev/null
+"$ENT" --histogram-svg "$work/cached.svg" --cache "$work/cache" "$work/data" > /dev/null
+cmp -s "$work/fresh.svg" "$work/cached.svg" || fail "SVG histogram from the cache differs"
+
+# A changed file is counted again, even when its size stays the same.
+printf 'HELLO WORLD' | dd of="$work/data" bs=1 seek=100000 conv=notrunc 2> /dev/null
+touch -d '2001-01-01' "$work/data"
+fresh=$("$ENT" -f ndjson --include-histogram "$work/data")
+cached=$("$ENT" -v -f ndjson --include-histogram --cache "$work/cache" "$work/data" 2> "$work/log")
+grep -q "using cached byte counts" "$work/log" && fail "a modified file was taken from the cache"
+expect_eq "$cached" "$fresh" "histogram after the file changed"
+
+finish
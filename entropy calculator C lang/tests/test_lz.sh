@@ -0,0 +1,26 @@
+#!/bin/sh
+# --lz counts the phrases of the Lempel-Ziv 76 factorization. Two inputs with
+# the same byte histogram, and so the same entropy, must still differ when one
+# repeats itself and the other does not.
+. "$(dirname "$0")/lib.sh"
+
+yes AB | tr -d '\n' | head -c 20000 > "$work/repetitive"
+fold -w1 "$work/repetitive" | shuf | tr -d '\n' > "$work/shuffled"
+
+repetitive=$("$ENT" -f ndjson --lz "$work/repetitive")
+shuffled=$("$ENT" -f ndjson --lz "$work/shuffled")
+expect_eq "$(json_field "$shuffled" byte_entropy)" "$(json_field "$repetitive" byte_entropy)" "same histogram"
+awk -v r="$(json_field "$repetitive" lz_complexity)" -v s="$(json_field "$shuffled" lz_complexity)" \
+    'BEGIN { exit !(r * 100 < s) }' || fail "ABAB... is not far below its shuffle in LZ complexity"
+
+# A, B, then one phrase copying the rest.
+expect_match "$("$ENT" --lz "$work/repetitive")" "Lempel-Ziv complexity +: [0-9.]+ \(3 phrases\)" "ABAB... phrases"
+# h|e|l|lo| |w|or|ld
+printf 'hello world' > "$work/text"
+expect_match "$("$ENT" --lz "$work/text")" "Lempel-Ziv complexity +: 0\.838650 \(8 phrases\)" "hello world phrases"
+
+head -c 65536 /dev/urandom > "$work/random"
+line=$("$ENT" -f ndjson --lz "$work/random")
+expect_near "$(json_field "$line" lz_complexity)" 1 0.2 "random data is near 1"
+
+finish
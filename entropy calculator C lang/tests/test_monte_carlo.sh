@@ -0,0 +1,30 @@
+#!/bin/sh
+# --monte-carlo turns each 6 bytes into a point (3 bytes for x, 3 for y), as
+# ent does, and estimates Pi from the share that falls inside the circle.
+# Random data lands close to Pi; constant data does not.
+. "$(dirname "$0")/lib.sh"
+
+head -c 1048576 /dev/urandom > "$work/random"
+line=$("$ENT" -f ndjson --monte-carlo "$work/random")
+expect_near "$(json_field "$line" pi_estimate)" 3.14159 0.1 "random data, estimate"
+expect_near "$(json_field "$line" pi_error_pct)" 0 3 "random data, error"
+
+# Every point at the origin is inside: 4, 27.32% off.
+head -c 60000 /dev/zero > "$work/zeros"
+line=$("$ENT" -f ndjson --monte-carlo "$work/zeros")
+expect_near "$(json_field "$line" pi_estimate)" 4 0 "zeros, estimate"
+expect_near "$(json_field "$line" pi_error_pct)" 27.3239545 1e-6 "zeros, error"
+
+# Every point in the far corner is outside: 0, 100% off.
+head -c 60000 /dev/zero | tr '\000' '\377' > "$work/ones"
+line=$("$ENT" -f ndjson --monte-carlo "$work/ones")
+expect_near "$(json_field "$line" pi_estimate)" 0 0 "0xff bytes, estimate"
+expect_near "$(json_field "$line" pi_error_pct)" 100 1e-9 "0xff bytes, error"
+
+# Fewer than 6 bytes make no point.
+printf 'abc' > "$work/short"
+line=$("$ENT" -f ndjson --monte-carlo "$work/short")
+expect_eq "$(json_field "$line" pi_estimate)" null "under 6 bytes"
+expect_match "$("$ENT" --monte-carlo "$work/short")" "Monte Carlo value for Pi +: n/a" "under 6 bytes, text"
+
+finish
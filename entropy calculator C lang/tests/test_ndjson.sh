@@ -0,0 +1,34 @@
+#!/bin/sh
+# ndjson is already compact: one object per line with no whitespace, and keys
+# in a fixed order. Options add keys without moving the others, so lines from
+# different runs can be diffed.
+. "$(dirname "$0")/lib.sh"
+
+# keys LINE prints the keys of an ndjson LINE in order, one per line
+keys() {
+    printf '%s\n' "$1" | grep -o '"[a-z0-9_]*":' | tr -d '":'
+}
+
+cd "$work"
+printf 'hello world' > text
+head -c 4096 /dev/urandom > random
+: > empty
+
+output=$("$ENT" -f ndjson text random empty 2> /dev/null || true)
+expect_eq "$(printf '%s\n' "$output" | wc -l | tr -d ' ')" 3 "one line per file"
+expect_eq "$(printf '%s\n' "$output" | sed 's/"[^"]*"//g' | grep -c '[[:space:]]')" 0 "no whitespace outside strings"
+
+base=$(keys "$(printf '%s\n' "$output" | sed -n 1p)")
+expect_eq "$(printf '%s\n' "$base" | sed -n 1p)" filename "filename comes first"
+expect_eq "$(keys "$(printf '%s\n' "$output" | sed -n 2p)")" "$base" "same keys for another file"
+expect_eq "$(keys "$(printf '%s\n' "$output" | sed -n 3p)")" "$(printf 'filename\nerror\nerror_kind')" "keys of a failed file"
+
+# Options only add keys; dropping them gives back the base order.
+line=$("$ENT" -f ndjson --delta --lz --monte-carlo text)
+extra=$(keys "$line" | grep -v -x -e delta_entropy -e lz_complexity -e pi_estimate -e pi_error_pct)
+expect_eq "$extra" "$base" "key order with options"
+
+# The same input gives the same line, byte for byte.
+expect_eq "$("$ENT" -f ndjson text)" "$(printf '%s\n' "$output" | sed -n 1p)" "repeat run"
+
+finish
@@ -0,0 +1,26 @@
+#!/bin/sh
+# normalized_entropy divides by the maximum of the entropy in use: 8 bits for
+# bytes, and log2(9) for -b, which counts the set bits of each byte (0 to 8).
+. "$(dirname "$0")/lib.sh"
+
+all_bytes > "$work/uniform"
+line=$("$ENT" -f ndjson "$work/uniform")
+expect_near "$(json_field "$line" normalized_entropy)" 1 1e-12 "every byte value once"
+expect_match "$("$ENT" "$work/uniform")" "Normalized entropy +: 100\.00% of maximum" "text output"
+
+# One byte of each popcount from 0 to 8 is uniform for -b, but not for bytes.
+printf '\000\001\003\007\017\037\077\177\377' > "$work/popcounts"
+line=$("$ENT" -f ndjson -b "$work/popcounts")
+expect_near "$(json_field "$line" normalized_entropy)" 1 1e-12 "-b on one byte of each popcount"
+line=$("$ENT" -f ndjson "$work/popcounts")
+expect_near "$(json_field "$line" normalized_entropy)" 0.396240625 1e-9 "bytes of the same file, log2(9) / 8"
+
+# All 256 values are not uniform over popcounts, so -b stays below 1.
+line=$("$ENT" -f ndjson -b "$work/uniform")
+expect_near "$(json_field "$line" normalized_entropy)" 0.8026 1e-4 "-b on every byte value once"
+
+printf 'aaaa' > "$work/constant"
+line=$("$ENT" -f ndjson "$work/constant")
+expect_near "$(json_field "$line" normalized_entropy)" 0 0 "constant file"
+
+finish
@@ -0,0 +1,23 @@
+#!/bin/sh
+# Counting a large file on several threads must give the same results as one
+# thread. The file is just over the 64 MiB at which --threads takes effect and
+# not a multiple of the thread count, so the last chunk is shorter.
+. "$(dirname "$0")/lib.sh"
+
+{
+    head -c 67108864 /dev/urandom
+    yes 'low entropy tail' | head -c 12345
+} > "$work/large"
+
+sequential=$("$ENT" --threads 1 -f ndjson --all-metrics --include-histogram "$work/large")
+for threads in 2 3 8; do
+    parallel=$("$ENT" --threads $threads -f ndjson --all-metrics --include-histogram "$work/large")
+    expect_eq "$parallel" "$sequential" "--threads $threads"
+done
+
+# Metrics that need the bytes themselves go through the same chunked read.
+sequential=$("$ENT" --threads 1 --conditional --delta-entropy "$work/large")
+parallel=$("$ENT" --threads 4 --conditional --delta-entropy "$work/large")
+expect_eq "$parallel" "$sequential" "--threads 4 --conditional --delta-entropy"
+
+finish
@@ -0,0 +1,28 @@
+#!/bin/sh
+# --relative-to reports each file's entropy per byte as a percentage of the
+# reference file's.
+. "$(dirname "$0")/lib.sh"
+
+printf 'abababab' > "$work/reference"     # 1 bit per byte
+printf 'abcdabcd' > "$work/double"        # 2 bits per byte
+printf 'aaaa' > "$work/constant"          # 0 bits per byte
+: > "$work/empty"
+
+line=$("$ENT" -f ndjson --relative-to "$work/reference" "$work/double")
+expect_near "$(json_field "$line" pct_of_reference)" 200 1e-9 "twice the reference's entropy"
+
+line=$("$ENT" -f ndjson --relative-to "$work/reference" "$work/reference")
+expect_near "$(json_field "$line" pct_of_reference)" 100 1e-9 "the reference against itself"
+
+text=$("$ENT" --relative-to "$work/reference" "$work/double")
+expect_match "$text" "Percent of reference +: 200\.00% of .*reference" "text output"
+
+# A reference without entropy gives no percentage rather than infinity.
+line=$("$ENT" -f ndjson --relative-to "$work/constant" "$work/double")
+expect_eq "$(json_field "$line" pct_of_reference)" null "a zero-entropy reference"
+
+# A reference that cannot be analyzed is an error, not a silent 0%.
+expect_status 1 "$ENT" --relative-to "$work/empty" "$work/double"
+expect_status 1 "$ENT" --relative-to "$work/missing" "$work/double"
+
+finish
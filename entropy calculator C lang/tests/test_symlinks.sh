@@ -0,0 +1,28 @@
+#!/bin/sh
+# The tool does not walk directories, so there is no traversal to follow
+# symlinks in: a symlink given directly (or through a quoted glob) is opened
+# like any path. These checks pin that a self-referential symlink ends in an
+# error instead of a hang, and that a symlinked directory is not descended.
+. "$(dirname "$0")/lib.sh"
+
+mkdir "$work/tree" "$work/tree/shared"
+printf 'hello world' > "$work/tree/shared/asset"
+ln -s loop "$work/tree/loop"
+ln -s shared "$work/tree/assets"
+ln -s shared/asset "$work/tree/link"
+
+line=$(timeout 10 "$ENT" -f ndjson "$work/tree/link")
+expect_near "$(json_field "$line" byte_entropy)" 2.8453509366 1e-9 "symlink to a file is followed"
+
+status=0
+line=$(timeout 10 "$ENT" -f ndjson "$work/tree/loop") || status=$?
+expect_eq "$status" 1 "self-referential symlink exit status"
+expect_match "$line" "Too many levels of symbolic links" "self-referential symlink error"
+
+status=0
+output=$(cd "$work/tree" && timeout 10 "$ENT" -f ndjson '*' 2> /dev/null) || status=$?
+expect_eq "$status" 1 "glob over a tree with a symlink loop"
+expect_eq "$(printf '%s\n' "$output" | wc -l | tr -d ' ')" 4 "one line per path in the glob"
+expect_match "$output" '"filename":"assets","error":"Error reading file: assets: Is a directory"' "symlinked directory"
+
+finish
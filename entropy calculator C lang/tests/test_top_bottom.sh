@@ -0,0 +1,33 @@
+#!/bin/sh
+# --top and --bottom trim the results after sorting; summaries such as
+# --entropy-histogram still cover every file.
+. "$(dirname "$0")/lib.sh"
+
+cd "$work"
+printf 'aaaa' > e0         # 0 bits
+printf 'abab' > e1         # 1 bit
+printf 'abcd' > e2         # 2 bits
+printf 'abcdefgh' > e3     # 3 bits
+: > empty
+
+# Without --sort, highest entropy first.
+expect_eq "$("$ENT" --entropy-only --top 2 e1 e3 e0 e2)" "$(printf '3.000000\te3\n2.000000\te2')" "--top 2"
+expect_eq "$("$ENT" --entropy-only --bottom 2 e1 e3 e0 e2)" "$(printf '1.000000\te1\n0.000000\te0')" "--bottom 2"
+
+# With --sort, trimmed in that order.
+expect_eq "$("$ENT" --entropy-only --sort name --top 2 e3 e1 e0 e2)" "$(printf '0.000000\te0\n1.000000\te1')" "--sort name --top 2"
+expect_eq "$("$ENT" --entropy-only --sort size --bottom 1 e3 e1 e0 e2)" "$(printf '3.000000\te3')" "--sort size --bottom 1"
+expect_eq "$("$ENT" --entropy-only --top 10 e1 e0)" "$(printf '1.000000\te1\n0.000000\te0')" "--top larger than the file count"
+
+# Files that failed are still reported, and not counted towards N.
+output=$("$ENT" -f ndjson --top 1 e0 empty e3 2> /dev/null || true)
+expect_eq "$(printf '%s\n' "$output" | grep -c '"error_kind":"empty"')" 1 "failed file is reported"
+expect_eq "$(printf '%s\n' "$output" | grep -c '"byte_entropy"')" 1 "one analyzed file"
+expect_match "$output" '"filename":"e3","size_bytes"' "the highest one"
+
+# The histogram counts all four files, not just the one printed.
+histogram=$("$ENT" --top 1 --entropy-histogram=4 e0 e1 e2 e3 2> /dev/null)
+expect_match "$histogram" "^0\.00 - 2\.00 \) .* 2$" "histogram, first bin"
+expect_match "$histogram" "^2\.00 - 4\.00 \) .* 2$" "histogram, second bin"
+
+finish